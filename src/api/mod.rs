@@ -1,6 +1,7 @@
 use std::collections::BTreeMap;
 
 use crate::model::*;
+use axum::extract::{Path, Query};
 use axum::http::StatusCode;
 use axum::{Extension, Json};
 
@@ -13,7 +14,7 @@ pub async fn predict(
 ) -> Result<Json<Response>, StatusCode> {
     let (status, msg) = req.check();
     if status {
-        let response = prediction_service.predict(&req);
+        let response = prediction_service.predict(&req).await;
         Ok(Json(response))
     } else {
         Ok(Json(Response {
@@ -24,6 +25,77 @@ pub async fn predict(
     }
 }
 
+pub async fn track_event(
+    Extension(event_service): Extension<EventService>,
+    Json(req): Json<EventRequest>,
+) -> Result<Json<EventResponse>, StatusCode> {
+    Ok(Json(do_track_events(&event_service, vec![req]).await))
+}
+
+pub async fn track_events(
+    Extension(event_service): Extension<EventService>,
+    Json(req): Json<BatchEventRequest>,
+) -> Result<Json<EventResponse>, StatusCode> {
+    Ok(Json(do_track_events(&event_service, req.events).await))
+}
+
+async fn do_track_events(event_service: &EventService, events: Vec<EventRequest>) -> EventResponse {
+    for event in &events {
+        let (status, msg) = event.check();
+        if !status {
+            return EventResponse {
+                code: 400,
+                msg,
+                accepted: 0,
+            };
+        }
+    }
+
+    match event_service.track(&events).await {
+        Ok(accepted) => EventResponse {
+            code: 0,
+            msg: "".to_string(),
+            accepted,
+        },
+        Err(e) => {
+            log::error!("track events error: {}", e);
+            EventResponse {
+                code: 500,
+                msg: e.to_string(),
+                accepted: 0,
+            }
+        }
+    }
+}
+
+pub async fn status(
+    Extension(leader): Extension<LeaderElector>,
+) -> Result<Json<LeaderStatus>, StatusCode> {
+    Ok(Json(leader.status().await))
+}
+
+/// 最近的动态配置变更
+pub async fn config_changes(
+    Extension(ads_db): Extension<AdsDB>,
+    Query(query): Query<CfgChangesQuery>,
+) -> Result<Json<Vec<CfgChange>>, StatusCode> {
+    let changes = ads_db
+        .dyn_cfg
+        .recent_changes(query.key.as_deref(), query.limit.unwrap_or(100));
+    Ok(Json(changes))
+}
+
+/// 实验版本的对照组/实验组分析报告
+pub async fn experiment_report(
+    Extension(analysis_service): Extension<AnalysisService>,
+    Path(version): Path<String>,
+    Query(query): Query<ReportQuery>,
+) -> Result<Json<ExperimentReport>, StatusCode> {
+    Ok(Json(
+        analysis_service.report(&version, query.min_samples).await,
+    ))
+}
+
 pub async fn test(
     Extension(ads_db): Extension<AdsDB>,
 ) -> Result<Json<BTreeMap<String, String>>, StatusCode> {
@@ -1,10 +1,11 @@
-use std::cmp::Ordering;
-use std::collections::HashSet;
-use std::str::FromStr;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
 use std::time::Duration;
 
 use chrono::DateTime;
+use chrono::FixedOffset;
 use chrono::Local;
+use chrono::Utc;
 use moka::sync::Cache;
 
 use crate::dao::*;
@@ -17,33 +18,187 @@ pub struct AdsDB {
     pub redis_dao: RedisDao,
     adid_cache: Cache<String, i64>,
     adid_experiment_cache: Cache<String, AdIdExpCfg>,
+    realtime_window: RealtimeWindow,
+    daily_bucket: DailyBucket,
+    signal_tables: Arc<SignalTables>,
+}
+
+/// 实时窗口配置: 窗口长度 = bucket_secs * buckets
+///
+/// 读取时每个广告读取全部桶, 一次预估的MGET约为 广告数 * (buckets + 2) 个key,
+/// 如默认60个桶、50个广告时约3000个key; 桶数越多窗口越平滑, 读取开销也按比例增长
+#[derive(Clone, Copy, Debug)]
+pub struct RealtimeWindow {
+    pub bucket_secs: i64,
+    pub buckets: i64,
+}
+
+impl Default for RealtimeWindow {
+    fn default() -> Self {
+        // 60个1分钟的桶
+        Self::new(60 * 60, 60)
+    }
+}
+
+impl RealtimeWindow {
+    pub fn new(window_secs: i64, bucket_secs: i64) -> Self {
+        let bucket_secs = bucket_secs.max(1);
+        Self {
+            bucket_secs,
+            buckets: (window_secs / bucket_secs).max(1),
+        }
+    }
+
+    pub fn bucket_of(&self, ts: i64) -> i64 {
+        ts / self.bucket_secs
+    }
+
+    /// 窗口内所有桶的key, 包括当前桶
+    pub fn bucket_keys(&self, usergroup: &str, ad_id: i64, now_ts: i64) -> Vec<String> {
+        let current = self.bucket_of(now_ts);
+        (0..self.buckets)
+            .map(|i| realtime_event_key(usergroup, ad_id, current - i))
+            .collect()
+    }
+
+    /// 桶的过期时间, 多保留一个桶避免窗口边界丢数据
+    pub fn ttl_secs(&self) -> usize {
+        ((self.buckets + 1) * self.bucket_secs) as usize
+    }
+}
+
+pub(crate) fn realtime_event_key(usergroup: &str, ad_id: i64, bucket: i64) -> String {
+    format!("realtime:event:{}:{}:{}", usergroup, ad_id, bucket)
+}
+
+pub(crate) fn user_daily_event_key(date: &str, usr: &str, ad_id: i64) -> String {
+    format!("daily:event:{}:{}:{}", date, usr, ad_id)
+}
+
+pub(crate) fn user_daily_tempt_click_key(date: &str, usr: &str) -> String {
+    format!("daily:tempclick:{}:{}", date, usr)
+}
+
+pub(crate) fn exp_group_event_key(version: &str, usergroup: &str, ad_id: i64) -> String {
+    format!("expversion:event:{}:{}:{}", version, usergroup, ad_id)
+}
+
+const EXP_EVENT_EXPIRE_TIME: usize = 5 * 3600 * 24;
+
+pub(crate) fn ad_total_event_key(ad_id: i64) -> String {
+    format!("total:event:{}", ad_id)
+}
+
+/// 广告在某个动作下的累计事件, bandit策略的后验, 不过期
+pub(crate) fn bandit_event_key(ad_id: i64, action_id: &str) -> String {
+    format!("bandit:event:{}:{}", ad_id, action_id)
+}
+
+/// 用户每日计数配置: 按指定时区切分自然日, 计数key按ttl过期
+#[derive(Clone, Copy, Debug)]
+pub struct DailyBucket {
+    pub utc_offset_secs: i32,
+    pub ttl_secs: usize,
+}
+
+impl Default for DailyBucket {
+    fn default() -> Self {
+        Self {
+            utc_offset_secs: Local::now().offset().local_minus_utc(),
+            ttl_secs: 2 * 3600 * 24,
+        }
+    }
+}
+
+impl DailyBucket {
+    pub fn new(utc_offset_secs: i32, ttl_secs: usize) -> Self {
+        Self {
+            utc_offset_secs,
+            ttl_secs,
+        }
+    }
+
+    /// 时刻对应的自然日, 格式 `%Y%m%d`
+    pub fn date_of(&self, now: DateTime<Utc>) -> String {
+        let offset =
+            FixedOffset::east_opt(self.utc_offset_secs).unwrap_or_else(|| FixedOffset::east(0));
+        now.with_timezone(&offset).format("%Y%m%d").to_string()
+    }
+}
+
+/// AdsDB的本地缓存与计数配置
+#[derive(Clone, Debug)]
+pub struct AdsDBCfg {
+    pub adid_cache_ttl: Duration,
+    pub adid_cache_tti: Duration,
+    pub exp_cfg_cache_ttl: Duration,
+    pub exp_cfg_cache_tti: Duration,
+    pub dyn_cfg_sync_cron: String,
+    pub realtime_window: RealtimeWindow,
+    pub daily_bucket: DailyBucket,
+}
+
+impl Default for AdsDBCfg {
+    fn default() -> Self {
+        Self {
+            adid_cache_ttl: Duration::from_secs(24 * 60 * 60),
+            adid_cache_tti: Duration::from_secs(24 * 60 * 60),
+            exp_cfg_cache_ttl: Duration::from_secs(24 * 60 * 60),
+            exp_cfg_cache_tti: Duration::from_secs(24 * 60 * 60),
+            dyn_cfg_sync_cron: DYN_CFG_SYNC_CRON.to_string(),
+            realtime_window: RealtimeWindow::default(),
+            daily_bucket: DailyBucket::default(),
+        }
+    }
 }
 
 impl AdsDB {
-    pub fn new(redis_client: redis::Client) -> Self {
+    #[cfg(test)]
+    pub async fn new(storage: StorageRef) -> Self {
+        Self::new_with_cfg(storage, &AdsDBCfg::default()).await
+    }
+
+    pub async fn new_with_cfg(storage: StorageRef, cfg: &AdsDBCfg) -> Self {
         let adid_cache = Cache::builder()
-            .time_to_live(Duration::from_secs(24 * 60 * 60)) // Time to live (TTL): 30 minutes
-            .time_to_idle(Duration::from_secs(24 * 60 * 60)) // Time to idle (TTI):  5 minutes
+            .time_to_live(cfg.adid_cache_ttl)
+            .time_to_idle(cfg.adid_cache_tti)
             .build(); // Create the cache.
 
         let adid_experiment_cache = Cache::builder()
-            .time_to_live(Duration::from_secs(24 * 60 * 60))
-            .time_to_idle(Duration::from_secs(24 * 60 * 60))
+            .time_to_live(cfg.exp_cfg_cache_ttl)
+            .time_to_idle(cfg.exp_cfg_cache_tti)
             .build(); // Create the cache.
 
+        let dyn_cfg = DyncConfigV2::new_with_cron(storage.clone(), &cfg.dyn_cfg_sync_cron).await;
+        let signal_tables = SignalTables::watch(&dyn_cfg);
+
         AdsDB {
-            dyn_cfg: DyncConfigV2::new(redis_client.clone()),
-            redis_dao: RedisDao::new(redis_client.clone()),
+            dyn_cfg,
+            redis_dao: RedisDao::new(storage),
             adid_cache,
             adid_experiment_cache,
+            realtime_window: cfg.realtime_window,
+            daily_bucket: cfg.daily_bucket,
+            signal_tables,
         }
     }
 
-    pub fn add_adids_to_localcache(&self, version: &str, ad_id: &Vec<i64>) {
+    /// 当前自然日, 作为用户每日计数的key
+    pub fn today(&self) -> String {
+        self.daily_bucket.date_of(Utc::now())
+    }
+
+    /// 返回本地缓存中新增的广告id
+    pub fn add_adids_to_localcache(&self, version: &str, ad_id: &Vec<i64>) -> Vec<i64> {
+        let mut new_ad_ids = Vec::new();
         for ad_id in ad_id {
-            self.adid_cache
-                .insert(format!("{}:{}", version, ad_id.to_string()), *ad_id);
+            let key = format!("{}:{}", version, ad_id);
+            if !self.adid_cache.contains_key(&key) {
+                self.adid_cache.insert(key, *ad_id);
+                new_ad_ids.push(*ad_id);
+            }
         }
+        new_ad_ids
     }
 
     pub fn get_version_adids_from_localcache(&self, version: &str) -> Vec<i64> {
@@ -56,35 +211,60 @@ impl AdsDB {
         ad_ids
     }
 
-    pub fn get_realtime_adids_event(&self, keys: Vec<&str>) -> Vec<AdEvent> {
-        let mut ad_events: Vec<AdEvent> = Vec::new();
-
-        match self.redis_dao.get_multi_event_by_keys(keys) {
-            Ok(events) => {
-                for event_str in events {
-                    let fields: Vec<_> = event_str.split("_").collect();
-                    if fields.len() < 4 {
-                        continue;
-                    }
-                    let ad_event = AdEvent {
-                        request: fields[0].parse().unwrap_or_default(),
-                        fill: fields[1].parse().unwrap_or_default(),
-                        show: fields[2].parse().unwrap_or_default(),
-                        click: fields[3].parse().unwrap_or_default(),
-                    };
-                    ad_events.push(ad_event);
-                }
+    /// 记录事件: 用户每日计数, 用户组实时窗口, 广告总计数, 一次原子更新
+    pub async fn record_events(&self, events: &[(&EventRequest, &str)]) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let date = self.daily_bucket.date_of(now);
+        let bucket = self.realtime_window.bucket_of(now.timestamp());
+        let realtime_ttl = self.realtime_window.ttl_secs();
+        let version = self.get_exp_base_cfg().version;
+
+        let mut incrs = Vec::with_capacity(events.len() * 3);
+        for (event, usergroup) in events {
+            let field = Some(event.event.index());
+            incrs.push(EventIncr {
+                key: user_daily_event_key(&date, &event.usr, event.ad_id),
+                field,
+                ttl: self.daily_bucket.ttl_secs,
+            });
+            incrs.push(EventIncr {
+                key: realtime_event_key(usergroup, event.ad_id, bucket),
+                field,
+                ttl: realtime_ttl,
+            });
+            incrs.push(EventIncr {
+                key: ad_total_event_key(event.ad_id),
+                field,
+                ttl: 0,
+            });
+            if !version.is_empty() {
+                incrs.push(EventIncr {
+                    key: exp_group_event_key(&version, usergroup, event.ad_id),
+                    field,
+                    ttl: EXP_EVENT_EXPIRE_TIME,
+                });
             }
-            Err(e) => {
-                log::error!("get_realtime_adids_event error: {}", e);
+            if let Some(action_id) = event.action_id.as_deref().filter(|a| !a.is_empty()) {
+                incrs.push(EventIncr {
+                    key: bandit_event_key(event.ad_id, action_id),
+                    field,
+                    ttl: 0,
+                });
+            }
+            if event.is_tempt_click() {
+                incrs.push(EventIncr {
+                    key: user_daily_tempt_click_key(&date, &event.usr),
+                    field: None,
+                    ttl: self.daily_bucket.ttl_secs,
+                });
             }
         }
 
-        ad_events
+        self.redis_dao.incr_event_counters(&incrs).await
     }
 
-    pub fn update_adids(&self, version: &str, ad_ids: Vec<i64>) {
-        match self.redis_dao.update_adids(version, ad_ids) {
+    pub async fn update_adids(&self, version: &str, ad_ids: Vec<i64>) {
+        match self.redis_dao.update_adids(version, ad_ids).await {
             Ok(_) => {}
             Err(e) => {
                 log::error!("update_adids error: {}", e);
@@ -92,162 +272,308 @@ impl AdsDB {
         }
     }
 
-    pub fn get_adid_exp_cfg(&self, version: &str, ad_id: i64) -> AdIdExpCfg {
-        let key = format!("{}:{}", version, ad_id);
+    /// 实验版本下所有出现过的广告id
+    pub async fn get_version_adids(&self, version: &str) -> Vec<i64> {
+        match self.redis_dao.get_adids(version).await {
+            Ok(ad_ids) => ad_ids,
+            Err(e) => {
+                log::error!("get_version_adids error: {}", e);
+                vec![]
+            }
+        }
+    }
 
-        let cfg = self.adid_experiment_cache.get(&key).unwrap_or_else(|| {
-            let r_cfg = match self.redis_dao.get_adid_exp_cfg(version, ad_id) {
-                Ok(cfg) => cfg,
-                Err(e) => {
-                    log::error!("get_adid_exp_cfg error: {}", e);
-                    AdIdExpCfg::default()
-                }
-            };
-            if !r_cfg.is_empty() {
-                self.adid_experiment_cache.insert(key, r_cfg.clone());
+    /// 实验版本下某个用户组对广告的累计事件
+    pub async fn get_exp_group_event(&self, version: &str, usergroup: &str, ad_id: i64) -> AdEvent {
+        let key = exp_group_event_key(version, usergroup, ad_id);
+        match self.redis_dao.get_multi_event_by_keys(vec![&key]).await {
+            Ok(events) => events
+                .first()
+                .and_then(|e| AdEvent::parse(e))
+                .unwrap_or_default(),
+            Err(e) => {
+                log::error!("get_exp_group_event error: {}", e);
+                AdEvent::default()
             }
-            r_cfg
-        });
+        }
+    }
 
-        cfg
+    /// 广告各动作的累计事件, 与 (ad_id, action_id) 一一对应, 一次MGET
+    pub async fn get_bandit_events(&self, arms: &[(i64, &str)]) -> Vec<AdEvent> {
+        let keys: Vec<String> = arms
+            .iter()
+            .map(|(ad_id, action_id)| bandit_event_key(*ad_id, action_id))
+            .collect();
+        match self
+            .redis_dao
+            .get_multi_event_by_keys(keys.iter().map(|k| k.as_str()).collect())
+            .await
+        {
+            Ok(values) => values
+                .iter()
+                .map(|v| AdEvent::parse(v).unwrap_or_default())
+                .chain(std::iter::repeat(AdEvent::default()))
+                .take(arms.len())
+                .collect(),
+            Err(e) => {
+                log::error!("get_bandit_events error: {}", e);
+                vec![AdEvent::default(); arms.len()]
+            }
+        }
     }
 
-    pub fn set_adid_exp_cfg(&self, version: &str, ad_id: i64, cfg: AdIdExpCfg) {
+    pub async fn get_target_ctr_actions(&self, ad_id: i64) -> BTreeMap<String, f64> {
+        match self.redis_dao.get_target_ctr_actions(ad_id).await {
+            Ok(actions) => actions,
+            Err(e) => {
+                log::error!("get_target_ctr_actions error: {}", e);
+                BTreeMap::new()
+            }
+        }
+    }
+
+    /// 批量读取多个广告的目标CTR动作, 结果与ad_ids一一对应
+    pub async fn get_multi_target_ctr_actions(&self, ad_ids: &[i64]) -> Vec<BTreeMap<String, f64>> {
+        match self.redis_dao.get_multi_target_ctr_actions(ad_ids).await {
+            Ok(actions) => actions,
+            Err(e) => {
+                log::error!("get_multi_target_ctr_actions error: {}", e);
+                vec![BTreeMap::new(); ad_ids.len()]
+            }
+        }
+    }
+
+    pub async fn set_ad_exp_action_score(
+        &self,
+        version: &str,
+        ad_id: i64,
+        scores: HashMap<String, i64>,
+    ) {
+        match self
+            .redis_dao
+            .set_ad_exp_action_score(version, ad_id, scores)
+            .await
+        {
+            Ok(_) => {}
+            Err(e) => log::error!("set_ad_exp_action_score error: {}", e),
+        }
+    }
+
+    /// fencing写入: lease不再是当前leader租约时不写入并返回false
+    pub async fn update_exp_base_cfg(&self, cfg: &ExpBaseCfg, lease: &str) -> anyhow::Result<bool> {
+        self.redis_dao.update_exp_base_cfg(cfg, lease).await
+    }
+
+    pub async fn get_adid_exp_cfg(&self, version: &str, ad_id: i64) -> AdIdExpCfg {
+        let key = format!("{}:{}", version, ad_id);
+
+        if let Some(cfg) = self.adid_experiment_cache.get(&key) {
+            return cfg;
+        }
+
+        let r_cfg = match self.redis_dao.get_adid_exp_cfg(version, ad_id).await {
+            Ok(cfg) => cfg,
+            Err(e) => {
+                log::error!("get_adid_exp_cfg error: {}", e);
+                AdIdExpCfg::default()
+            }
+        };
+        if !r_cfg.is_empty() {
+            self.adid_experiment_cache.insert(key, r_cfg.clone());
+        }
+        r_cfg
+    }
+
+    pub async fn set_adid_exp_cfg(&self, version: &str, ad_id: i64, cfg: AdIdExpCfg) {
         let key = format!("{}:{}", version, ad_id);
         self.adid_experiment_cache.insert(key, cfg.clone());
-        match self.redis_dao.set_adid_exp_cfg(version, ad_id, &cfg) {
+        match self.redis_dao.set_adid_exp_cfg(version, ad_id, &cfg).await {
             Ok(_) => {}
             Err(err) => log::error!("update_adids error: {}", err),
         }
     }
 
-    pub(crate) fn get_user_daily_ad_id_event(
-        &self,
-        _adid: i64,
-        _usr: &str,
-        _date: &str,
-    ) -> AdEvent {
-        todo!()
+    /// 用户当日诱导点击总数, 跨天自动切换到新的key
+    pub(crate) async fn query_temp_click(&self, usr: &str) -> f64 {
+        let key = user_daily_tempt_click_key(&self.today(), usr);
+        match self.redis_dao.get_counter(&key).await {
+            Ok(count) => count as f64,
+            Err(e) => {
+                log::error!("query_temp_click error: {}", e);
+                0.0
+            }
+        }
     }
 
-    pub(crate) fn query_temp_click(&self, _usr: &str) -> f64 {
-        1.0
-        //todo!()
+    /// 批量加载广告的实时窗口计数, 用户当日计数和实验配置
+    /// 所有key合并为一次MGET, 本地已缓存的实验配置不再读取, key数量见 `RealtimeWindow`
+    pub(crate) async fn load_ad_signals(
+        &self,
+        usergroup: &str,
+        usr: &str,
+        version: &str,
+        ad_ids: &[i64],
+    ) -> Vec<AdSignals> {
+        let now = Utc::now();
+        let date = self.daily_bucket.date_of(now);
+        let buckets = self.realtime_window.buckets as usize;
+
+        let mut signals: Vec<AdSignals> = ad_ids
+            .iter()
+            .map(|ad_id| AdSignals {
+                ad_id: *ad_id,
+                exp_cfg: self
+                    .adid_experiment_cache
+                    .get(&format!("{}:{}", version, ad_id))
+                    .unwrap_or_default(),
+                ..Default::default()
+            })
+            .collect();
+
+        let mut keys: Vec<String> = Vec::with_capacity(ad_ids.len() * (buckets + 2));
+        for ad_id in ad_ids {
+            keys.extend(
+                self.realtime_window
+                    .bucket_keys(usergroup, *ad_id, now.timestamp()),
+            );
+        }
+        for ad_id in ad_ids {
+            keys.push(user_daily_event_key(&date, usr, *ad_id));
+        }
+        let missing_cfg: Vec<usize> = signals
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.exp_cfg.is_empty())
+            .map(|(i, _)| i)
+            .collect();
+        for i in &missing_cfg {
+            keys.push(adid_exp_cfg_key(version, ad_ids[*i]));
+        }
+
+        let values = match self
+            .redis_dao
+            .get_multi_event_by_keys(keys.iter().map(|k| k.as_str()).collect())
+            .await
+        {
+            Ok(values) if values.len() == keys.len() => values,
+            Ok(values) => {
+                log::error!(
+                    "load_ad_signals error: expect {} values, got {}",
+                    keys.len(),
+                    values.len()
+                );
+                return signals;
+            }
+            Err(e) => {
+                log::error!("load_ad_signals error: {}", e);
+                return signals;
+            }
+        };
+
+        let (realtime_values, rest) = values.split_at(ad_ids.len() * buckets);
+        let (daily_values, cfg_values) = rest.split_at(ad_ids.len());
+        for (i, signal) in signals.iter_mut().enumerate() {
+            for value in &realtime_values[i * buckets..(i + 1) * buckets] {
+                if let Some(event) = AdEvent::parse(value) {
+                    signal.realtime_event.merge(&event);
+                }
+            }
+            signal.daily_event = AdEvent::parse(&daily_values[i]).unwrap_or_default();
+        }
+        for (i, value) in missing_cfg.iter().zip(cfg_values) {
+            if value.is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<AdIdExpCfg>(value) {
+                Ok(cfg) if !cfg.is_empty() => {
+                    self.adid_experiment_cache
+                        .insert(format!("{}:{}", version, ad_ids[*i]), cfg.clone());
+                    signals[*i].exp_cfg = cfg;
+                }
+                Ok(_) => {}
+                Err(e) => log::error!("load_ad_signals parse exp cfg error: {}", e),
+            }
+        }
+
+        signals
     }
 
     /// =================================================
     /// 动态配置相关
     pub(crate) fn get_exp_base_cfg(&self) -> ExpBaseCfg {
-        let base_cfg_map = self.dyn_cfg.get_hash(super::RedisCfgKey_ExpBaseCfg);
-
-        ExpBaseCfg {
-            version: read_parse(base_cfg_map.get("version")),
-            base_value: read_parse(base_cfg_map.get("base")),
-            score_factor: read_parse(base_cfg_map.get("score_factor")),
-            start_time: get_date(base_cfg_map.get("start_time")),
-        }
+        self.dyn_cfg.get_cfg(super::RedisCfgKey_ExpBaseCfg)
     }
 
     pub(crate) fn get_exp_ab_params(&self) -> AbParams {
-        let base_cfg_map = self.dyn_cfg.get_hash(super::RedisCfgKey_ExpExpAbParams);
-
-        AbParams {
-            fill_a: read_parse(base_cfg_map.get("fill_a")),
-            fill_b: read_parse(base_cfg_map.get("fill_b")),
-            show_a: read_parse(base_cfg_map.get("show_a")),
-            show_b: read_parse(base_cfg_map.get("show_b")),
-            click_a: read_parse(base_cfg_map.get("click_a")),
-            click_b: read_parse(base_cfg_map.get("click_b")),
-        }
+        self.dyn_cfg.get_cfg(super::RedisCfgKey_ExpExpAbParams)
     }
 
-    #[allow(dead_code)]
-    pub(crate) fn get_adid_whitelist(&self) -> HashSet<u64> {
-        HashSet::new()
+    pub(crate) fn get_exp_driver_cfg(&self) -> ExpDriverCfg {
+        self.dyn_cfg.get_cfg(super::RedisCfgKey_ExpDriverCfg)
     }
 
-    pub(crate) fn get_signal_ad_id_fill_rate(&self) -> Vec<RangeValue> {
-        self.get_signal_cfg(super::RedisCfgKey_ExpSignalAdIdFillRate)
+    pub(crate) fn get_predict_cfg(&self) -> PredictCfg {
+        self.dyn_cfg.get_cfg(super::RedisCfgKey_PredictCfg)
     }
 
-    pub(crate) fn get_signal_ad_id_show_rate(&self) -> Vec<RangeValue> {
-        self.get_signal_cfg(super::RedisCfgKey_ExpSignalAdIdShowRate)
+    pub(crate) fn get_ctr_control_cfg(&self) -> CtrControlCfg {
+        self.dyn_cfg.get_cfg(super::RedisCfgKey_CtrControl)
     }
 
-    pub(crate) fn get_signal_ad_id_click_rate(&self) -> Vec<RangeValue> {
-        self.get_signal_cfg(super::RedisCfgKey_ExpSignalAdIdClickRate)
+    pub(crate) fn get_bucketing_cfg(&self) -> BucketingCfg {
+        self.dyn_cfg.get_cfg(super::RedisCfgKey_Bucketing)
     }
 
-    pub(crate) fn get_signal_daily_total_tempt_click(&self) -> Vec<RangeValue> {
-        self.get_signal_cfg(super::RedisCfgKey_ExpSignalDailyTotalTemptClick)
+    pub(crate) fn get_bandit_cfg(&self) -> BanditCfg {
+        self.dyn_cfg.get_cfg(super::RedisCfgKey_Bandit)
     }
 
-    pub(crate) fn get_realtime_ad_id_window_events(&self, _usergroup: &str, _adid: i64) -> AdEvent {
-        todo!()
+    pub(crate) fn get_shadow_cfg(&self) -> ShadowCfg {
+        self.dyn_cfg.get_cfg(super::RedisCfgKey_Shadow)
     }
 
-    fn get_signal_cfg(&self, key: &str) -> Vec<RangeValue> {
-        let cfgs = self.dyn_cfg.get_hash(key);
-
-        let mut range_cfgs = Vec::new();
-        for (k, v) in cfgs {
-            if !k.contains("_") {
-                continue;
-            }
+    /// service_type的默认策略, 未配置时取 `default` 字段
+    pub(crate) fn get_default_strategy(&self, service_type: i64) -> Option<String> {
+        let cfg = self.dyn_cfg.get_hash(super::RedisCfgKey_StrategyDefault);
+        cfg.get(&service_type.to_string())
+            .or_else(|| cfg.get("default"))
+            .filter(|name| !name.is_empty())
+            .cloned()
+    }
 
-            let mut range_keys = k.split("_");
-            let a = range_keys.next();
-            let b = range_keys.next();
-            range_cfgs.push(RangeValue {
-                min: a.unwrap_or_default().parse::<f64>().unwrap_or_default(),
-                max: b.unwrap_or_default().parse::<f64>().unwrap_or_default(),
-                value: v.parse().unwrap_or_default(),
-            });
-        }
+    #[allow(dead_code)]
+    pub(crate) fn get_adid_whitelist(&self) -> HashSet<u64> {
+        HashSet::new()
+    }
 
-        range_cfgs.sort_by(|a, b| -> Ordering {
-            if a.max > b.max {
-                return Ordering::Less;
-            } else if a.min > b.min {
-                return Ordering::Greater;
-            } else {
-                return Ordering::Equal;
-            }
-        });
+    pub(crate) fn get_signal_ad_id_fill_rate(&self) -> Arc<RangeTable> {
+        self.signal_tables.fill_rate()
+    }
 
-        range_cfgs
+    pub(crate) fn get_signal_ad_id_show_rate(&self) -> Arc<RangeTable> {
+        self.signal_tables.show_rate()
     }
-}
 
-fn read_parse<T>(s: Option<&String>) -> T
-where
-    T: FromStr + Default,
-{
-    match s {
-        Some(s) => s.parse().unwrap_or_default(),
-        None => T::default(),
+    pub(crate) fn get_signal_ad_id_click_rate(&self) -> Arc<RangeTable> {
+        self.signal_tables.click_rate()
     }
-}
 
-fn get_date(s: Option<&String>) -> DateTime<Local> {
-    match s {
-        Some(s) => s.parse::<DateTime<Local>>().unwrap_or(Local::now()),
-        None => Local::now(),
+    pub(crate) fn get_signal_daily_total_tempt_click(&self) -> Arc<RangeTable> {
+        self.signal_tables.tempt_click()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::collections::HashMap;
+    use std::sync::Arc;
 
     use moka::sync::ConcurrentCacheExt;
 
     use super::*;
 
-    #[tokio::test]
+    #[tokio::test(flavor = "multi_thread")]
     async fn test_adsdb_create() {
-        let redis_client = redis::Client::open("redis://127.0.0.1").unwrap();
-        let ads_db = AdsDB::new(redis_client.clone());
+        let ads_db = AdsDB::new(Arc::new(MemoryStorage::new())).await;
         assert_eq!(ads_db.adid_cache.entry_count(), 0);
         ads_db.add_adids_to_localcache("1", &vec![1, 2, 3]);
         ads_db.adid_cache.sync();
@@ -263,6 +589,62 @@ mod tests {
         ads_db.adid_cache.invalidate_all();
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_record_and_load_events() {
+        let ads_db = AdsDB::new(Arc::new(MemoryStorage::new())).await;
+        let events = [
+            EventRequest {
+                usr: "u1".to_string(),
+                ad_id: 12,
+                event: EventType::Request,
+                temptation: None,
+                action_id: None,
+            },
+            EventRequest {
+                usr: "u1".to_string(),
+                ad_id: 12,
+                event: EventType::Click,
+                temptation: Some(true),
+                action_id: Some("x".to_string()),
+            },
+        ];
+        let items: Vec<(&EventRequest, &str)> = events.iter().map(|e| (e, "a")).collect();
+        ads_db.record_events(&items).await.unwrap();
+
+        let signals = ads_db.load_ad_signals("a", "u1", "1", &[12, 13]).await;
+        assert_eq!(signals.len(), 2);
+        assert_eq!(signals[0].realtime_event.request, 1);
+        assert_eq!(signals[0].daily_event.click, 1);
+        assert_eq!(signals[1].daily_event, AdEvent::default());
+        assert_eq!(ads_db.query_temp_click("u1").await, 1.0);
+        assert_eq!(ads_db.query_temp_click("u2").await, 0.0);
+
+        let bandit = ads_db.get_bandit_events(&[(12, "x"), (12, "y")]).await;
+        assert_eq!(bandit[0].click, 1);
+        assert_eq!(bandit[0].request, 0);
+        assert_eq!(bandit[1], AdEvent::default());
+    }
+
+    #[test]
+    fn test_realtime_window_keys() {
+        let window = RealtimeWindow::new(5 * 60, 60);
+        assert_eq!(window.buckets, 5);
+        assert_eq!(window.ttl_secs(), 6 * 60);
+
+        let keys = window.bucket_keys("a", 12, 600);
+        assert_eq!(keys.len(), 5);
+        assert_eq!(keys[0], "realtime:event:a:12:10");
+        assert_eq!(keys[4], "realtime:event:a:12:6");
+    }
+
+    #[test]
+    fn test_daily_bucket_date() {
+        let now = "2022-05-30T17:30:00Z".parse::<DateTime<Utc>>().unwrap();
+        assert_eq!(DailyBucket::new(0, 60).date_of(now), "20220530");
+        assert_eq!(DailyBucket::new(8 * 3600, 60).date_of(now), "20220531");
+        assert_eq!(DailyBucket::new(-8 * 3600, 60).date_of(now), "20220530");
+    }
+
     #[test]
     fn test_hashmap() {
         let mut map = HashMap::new();
@@ -282,8 +664,7 @@ mod tests {
     fn test_get_exp_base_cfg() {
         let rt = tokio::runtime::Runtime::new().unwrap();
         rt.block_on(async {
-            let redis_client = redis::Client::open("redis://127.0.0.1").unwrap();
-            let ads_db = AdsDB::new(redis_client.clone());
+            let ads_db = AdsDB::new(Arc::new(MemoryStorage::new())).await;
             let cfg = ads_db.get_exp_base_cfg();
             println!("{:?}", cfg);
         });
@@ -293,11 +674,10 @@ mod tests {
     fn test_get_signal_cfg() {
         let rt = tokio::runtime::Runtime::new().unwrap();
         rt.block_on(async {
-            let redis_client = redis::Client::open("redis://127.0.0.1").unwrap();
-            let ads_db = AdsDB::new(redis_client.clone());
-            let cfg = ads_db.get_signal_cfg("cfg:signal:adid:fillrate");
+            let ads_db = AdsDB::new(Arc::new(MemoryStorage::new())).await;
+            let cfg = ads_db.get_signal_ad_id_fill_rate();
 
-            for c in cfg {
+            for c in cfg.ranges() {
                 println!("{:?}", c);
             }
         });
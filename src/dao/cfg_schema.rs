@@ -0,0 +1,255 @@
+use std::{collections::BTreeMap, fmt::Display, str::FromStr};
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Local};
+
+use super::HashCfg;
+use crate::model::*;
+
+/// 读取并解析hash字段, 字段不存在时返回None, 格式错误时报错
+fn field<T>(hash: &BTreeMap<String, String>, name: &str) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match hash.get(name) {
+        Some(v) => v
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|e| anyhow!("{}={:?}: {}", name, v, e)),
+        None => Ok(None),
+    }
+}
+
+fn check_non_negative(name: &str, v: f64) -> Result<()> {
+    if !v.is_finite() || v < 0.0 {
+        bail!("{}={} must be a non-negative number", name, v);
+    }
+    Ok(())
+}
+
+impl HashCfg for ExpBaseCfg {
+    fn from_hash(hash: &BTreeMap<String, String>) -> Result<Self> {
+        let default = Self::default();
+        let cfg = Self {
+            version: field::<String>(hash, "version")?.unwrap_or_default(),
+            base_value: field(hash, "base")?.unwrap_or(default.base_value),
+            score_factor: field(hash, "score_factor")?.unwrap_or(default.score_factor),
+            start_time: field::<DateTime<Local>>(hash, "start_time")?
+                .ok_or_else(|| anyhow!("start_time is required"))?,
+        };
+
+        if cfg.version.is_empty() {
+            bail!("version is required");
+        }
+        check_non_negative("base", cfg.base_value)?;
+        if !cfg.score_factor.is_finite() || cfg.score_factor <= 0.0 {
+            bail!("score_factor={} must be positive", cfg.score_factor);
+        }
+        Ok(cfg)
+    }
+}
+
+impl HashCfg for AbParams {
+    fn from_hash(hash: &BTreeMap<String, String>) -> Result<Self> {
+        let cfg = Self {
+            fill_a: field(hash, "fill_a")?.unwrap_or_default(),
+            fill_b: field(hash, "fill_b")?.unwrap_or_default(),
+            show_a: field(hash, "show_a")?.unwrap_or_default(),
+            show_b: field(hash, "show_b")?.unwrap_or_default(),
+            click_a: field(hash, "click_a")?.unwrap_or_default(),
+            click_b: field(hash, "click_b")?.unwrap_or_default(),
+        };
+
+        for (name, v) in [
+            ("fill_a", cfg.fill_a),
+            ("fill_b", cfg.fill_b),
+            ("show_a", cfg.show_a),
+            ("show_b", cfg.show_b),
+            ("click_a", cfg.click_a),
+            ("click_b", cfg.click_b),
+        ] {
+            check_non_negative(name, v)?;
+        }
+        Ok(cfg)
+    }
+}
+
+impl HashCfg for ExpDriverCfg {
+    fn from_hash(hash: &BTreeMap<String, String>) -> Result<Self> {
+        let default = Self::default();
+        let cfg = Self {
+            min_requests: field(hash, "min_requests")?.unwrap_or(default.min_requests),
+            cg_user: field(hash, "cg_user")?.unwrap_or(default.cg_user),
+            eg_user: field(hash, "eg_user")?.unwrap_or(default.eg_user),
+            max_age_secs: field(hash, "max_age_secs")?.unwrap_or(default.max_age_secs),
+        };
+
+        if cfg.min_requests <= 0 {
+            bail!("min_requests={} must be positive", cfg.min_requests);
+        }
+        if cfg.max_age_secs <= 0 {
+            bail!("max_age_secs={} must be positive", cfg.max_age_secs);
+        }
+        if cfg.cg_user.is_empty() || cfg.eg_user.is_empty() {
+            bail!("cg_user and eg_user are required");
+        }
+        if cfg.cg_user == cfg.eg_user {
+            bail!("cg_user and eg_user must differ, both are {}", cfg.cg_user);
+        }
+        Ok(cfg)
+    }
+}
+
+impl HashCfg for ShadowCfg {
+    fn from_hash(hash: &BTreeMap<String, String>) -> Result<Self> {
+        let cfg = Self {
+            rate: field(hash, "rate")?.unwrap_or_default(),
+            strategies: hash
+                .get("strategies")
+                .map(|v| {
+                    v.split(',')
+                        .map(str::trim)
+                        .filter(|s| !s.is_empty())
+                        .map(String::from)
+                        .collect()
+                })
+                .unwrap_or_default(),
+        };
+
+        if !(0.0..=1.0).contains(&cfg.rate) {
+            bail!("rate={} must be in [0, 1]", cfg.rate);
+        }
+        Ok(cfg)
+    }
+}
+
+impl HashCfg for BanditCfg {
+    fn from_hash(hash: &BTreeMap<String, String>) -> Result<Self> {
+        let default = Self::default();
+        let cfg = Self {
+            ucb_c: field(hash, "ucb_c")?.unwrap_or(default.ucb_c),
+            epsilon: field(hash, "epsilon")?.unwrap_or(default.epsilon),
+        };
+
+        check_non_negative("ucb_c", cfg.ucb_c)?;
+        if !(0.0..=1.0).contains(&cfg.epsilon) {
+            bail!("epsilon={} must be in [0, 1]", cfg.epsilon);
+        }
+        Ok(cfg)
+    }
+}
+
+impl HashCfg for BucketingCfg {
+    fn from_hash(hash: &BTreeMap<String, String>) -> Result<Self> {
+        BucketingCfg::parse(hash)
+    }
+}
+
+impl HashCfg for CtrControlCfg {
+    fn from_hash(hash: &BTreeMap<String, String>) -> Result<Self> {
+        CtrControlCfg::parse(hash)
+    }
+}
+
+impl HashCfg for PredictCfg {
+    fn from_hash(hash: &BTreeMap<String, String>) -> Result<Self> {
+        let default = Self::default();
+        let draw = match hash.get("draw").map(|v| v.trim()) {
+            None | Some("random") => DrawMode::Random,
+            Some("seeded") => DrawMode::Seeded,
+            Some(other) => bail!("draw={:?}, expect random or seeded", other),
+        };
+        let cfg = Self {
+            draw,
+            draw_bucket_secs: field(hash, "draw_bucket_secs")?.unwrap_or(default.draw_bucket_secs),
+        };
+
+        if cfg.draw_bucket_secs <= 0 {
+            bail!("draw_bucket_secs={} must be positive", cfg.draw_bucket_secs);
+        }
+        Ok(cfg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exp_base_cfg_schema() {
+        let cfg = ExpBaseCfg::from_hash(&hash(&[
+            ("version", "1.0.1"),
+            ("base", "0.3"),
+            ("start_time", "2022-05-30 10:27:47+0800"),
+        ]))
+        .unwrap();
+        assert_eq!(cfg.version, "1.0.1");
+        assert_eq!(cfg.base_value, 0.3);
+        assert_eq!(cfg.score_factor, 1.0);
+
+        assert!(ExpBaseCfg::from_hash(&hash(&[("base", "0.3")])).is_err());
+        // 缺少start_time时不能用解析时间代替, 否则每次加载版本都重新计时
+        assert!(ExpBaseCfg::from_hash(&hash(&[("version", "1")])).is_err());
+        assert!(ExpBaseCfg::from_hash(&hash(&[("version", "1"), ("base", "abc")])).is_err());
+        assert!(ExpBaseCfg::from_hash(&hash(&[("version", "1"), ("score_factor", "0")])).is_err());
+    }
+
+    #[test]
+    fn test_ab_params_schema() {
+        let cfg = AbParams::from_hash(&hash(&[("click_a", "1"), ("click_b", "20")])).unwrap();
+        assert_eq!(cfg.click_a, 1.0);
+        assert_eq!(cfg.click_b, 20.0);
+        assert_eq!(cfg.fill_a, 0.0);
+
+        assert!(AbParams::from_hash(&hash(&[("show_b", "-1")])).is_err());
+        assert!(AbParams::from_hash(&hash(&[("show_b", "NaN")])).is_err());
+    }
+
+    #[test]
+    fn test_exp_driver_cfg_schema() {
+        let cfg = ExpDriverCfg::from_hash(&hash(&[("min_requests", "500")])).unwrap();
+        assert_eq!(cfg.min_requests, 500);
+        assert_eq!(cfg.cg_user, "0");
+
+        assert_eq!(cfg.max_age_secs, 3 * 24 * 3600);
+
+        assert!(ExpDriverCfg::from_hash(&hash(&[("min_requests", "0")])).is_err());
+        assert!(ExpDriverCfg::from_hash(&hash(&[("max_age_secs", "-1")])).is_err());
+        assert!(ExpDriverCfg::from_hash(&hash(&[("cg_user", "1")])).is_err());
+    }
+
+    #[test]
+    fn test_predict_cfg_schema() {
+        let cfg = PredictCfg::from_hash(&hash(&[("draw", "seeded")])).unwrap();
+        assert_eq!(cfg.draw, DrawMode::Seeded);
+        assert_eq!(cfg.draw_bucket_secs, 3600);
+
+        assert!(PredictCfg::from_hash(&hash(&[("draw", "dice")])).is_err());
+        assert!(PredictCfg::from_hash(&hash(&[("draw_bucket_secs", "0")])).is_err());
+    }
+
+    #[test]
+    fn test_bandit_cfg_schema() {
+        let cfg = BanditCfg::from_hash(&hash(&[("epsilon", "0.2")])).unwrap();
+        assert_eq!(cfg.epsilon, 0.2);
+        assert_eq!(cfg.ucb_c, std::f64::consts::SQRT_2);
+
+        assert!(BanditCfg::from_hash(&hash(&[("epsilon", "1.5")])).is_err());
+        assert!(BanditCfg::from_hash(&hash(&[("ucb_c", "-1")])).is_err());
+    }
+
+    #[test]
+    fn test_shadow_cfg_schema() {
+        let cfg = ShadowCfg::from_hash(&hash(&[
+            ("rate", "0.05"),
+            ("strategies", "ucb1, thompson,"),
+        ]))
+        .unwrap();
+        assert_eq!(cfg.rate, 0.05);
+        assert_eq!(cfg.strategies, vec!["ucb1", "thompson"]);
+
+        assert!(ShadowCfg::from_hash(&hash(&[("rate", "2")])).is_err());
+    }
+}
@@ -1,45 +1,130 @@
 use std::{
-    collections::{BTreeMap, HashMap},
-    str,
-    sync::{Arc, RwLock},
+    any::Any,
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt, str,
+    sync::{Arc, Mutex, RwLock},
 };
 
-use redis::Commands;
+use chrono::{DateTime, Local};
+use serde::Serialize;
 use tokio_cron_scheduler::{Job, JobScheduler};
 
+use super::{Notification, StorageRef};
+use crate::model::*;
+
+/// 默认每10秒同步一次
+pub const DYN_CFG_SYNC_CRON: &str = "*/10 * * * * *";
+/// 保留最近的配置变更条数
+const CFG_CHANGE_HISTORY: usize = 256;
+
 /// 使用enum来实现多种配置管理
 #[derive(Clone, Debug)]
 pub enum CfgFieldField {
     Str(String),
     Int64(i64),
-    Float64(f64),
     Hash(BTreeMap<String, String>),
+    Typed(TypedCfg),
+}
+
+impl CfgFieldField {
+    /// 变更记录中展示的值, hash按json输出
+    fn render(&self) -> String {
+        match self {
+            CfgFieldField::Str(v) => v.clone(),
+            CfgFieldField::Int64(v) => v.to_string(),
+            CfgFieldField::Hash(v) => serde_json::to_string(v).unwrap_or_default(),
+            CfgFieldField::Typed(v) => serde_json::to_string(&v.raw).unwrap_or_default(),
+        }
+    }
+}
+
+/// 一次配置变更
+#[derive(Debug, Clone, Serialize)]
+pub struct CfgChange {
+    pub key: String,
+    pub old: String,
+    pub new: String,
+    pub timestamp: DateTime<Local>,
+}
+
+pub type CfgListener = Arc<dyn Fn(&CfgChange) + Send + Sync>;
+
+/// 由hash解析并校验的类型化配置, hash为空时使用默认值
+pub trait HashCfg: Default + Clone + Send + Sync + 'static {
+    fn from_hash(hash: &BTreeMap<String, String>) -> anyhow::Result<Self>;
+}
+
+type AnyCfg = Arc<dyn Any + Send + Sync>;
+
+/// 类型化配置的最后一个合法值及其原始hash
+#[derive(Clone)]
+pub struct TypedCfg {
+    raw: BTreeMap<String, String>,
+    value: AnyCfg,
+    parse: fn(&BTreeMap<String, String>) -> anyhow::Result<AnyCfg>,
+}
+
+impl fmt::Debug for TypedCfg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Typed({:?})", self.raw)
+    }
+}
+
+fn parse_typed<T: HashCfg>(hash: &BTreeMap<String, String>) -> anyhow::Result<AnyCfg> {
+    if hash.is_empty() {
+        return Ok(Arc::new(T::default()));
+    }
+    Ok(Arc::new(T::from_hash(hash)?))
 }
 
 #[derive(Clone)]
 pub struct DyncConfigV2 {
-    redis_client: redis::Client,
+    storage: StorageRef,
     fields: Arc<RwLock<HashMap<String, CfgFieldField>>>,
+    /// 最近一次被拒绝的原始值, 同一个错误值只记录一次
+    rejected: Arc<RwLock<HashMap<String, BTreeMap<String, String>>>>,
+    listeners: Arc<RwLock<HashMap<String, Vec<CfgListener>>>>,
+    /// 最近的变更, 最早的在前
+    changes: Arc<Mutex<VecDeque<CfgChange>>>,
+    /// 串行化读取与生效, 避免轮询读到的旧值覆盖通知后读到的新值
+    sync_lock: Arc<tokio::sync::Mutex<()>>,
 }
 
 impl DyncConfigV2 {
-    pub fn new(redis_client: redis::Client) -> Self {
+    #[cfg(test)]
+    pub async fn new(storage: StorageRef) -> Self {
+        Self::new_with_cron(storage, DYN_CFG_SYNC_CRON).await
+    }
+
+    pub async fn new_with_cron(storage: StorageRef, sync_cron: &str) -> Self {
         let dyn_cfg = Self {
-            redis_client: redis_client,
+            storage,
             fields: Arc::new(RwLock::new(HashMap::new())),
+            rejected: Arc::new(RwLock::new(HashMap::new())),
+            listeners: Arc::new(RwLock::new(HashMap::new())),
+            changes: Arc::new(Mutex::new(VecDeque::with_capacity(CFG_CHANGE_HISTORY))),
+            sync_lock: Arc::new(tokio::sync::Mutex::new(())),
         };
 
         dyn_cfg.add_str_field(super::RedisCfgKey_MasterServer.to_string());
         dyn_cfg.add_i64_field(super::RedisCfgKey_MainActionRate.to_string());
-        dyn_cfg.add_hash_field(super::RedisCfgKey_ExpBaseCfg.to_string());
+        dyn_cfg.add_typed_field::<ExpBaseCfg>(super::RedisCfgKey_ExpBaseCfg.to_string());
+        dyn_cfg.add_typed_field::<AbParams>(super::RedisCfgKey_ExpExpAbParams.to_string());
         dyn_cfg.add_hash_field(super::RedisCfgKey_ExpSignalDailyTotalTemptClick.to_string());
         dyn_cfg.add_hash_field(super::RedisCfgKey_ExpSignalAdIdFillRate.to_string());
         dyn_cfg.add_hash_field(super::RedisCfgKey_ExpSignalAdIdClickRate.to_string());
         dyn_cfg.add_hash_field(super::RedisCfgKey_ExpSignalAdIdShowRate.to_string());
+        dyn_cfg.add_hash_field(super::RedisCfgKey_StrategyDefault.to_string());
+        dyn_cfg.add_typed_field::<ExpDriverCfg>(super::RedisCfgKey_ExpDriverCfg.to_string());
+        dyn_cfg.add_typed_field::<PredictCfg>(super::RedisCfgKey_PredictCfg.to_string());
+        dyn_cfg.add_typed_field::<CtrControlCfg>(super::RedisCfgKey_CtrControl.to_string());
+        dyn_cfg.add_typed_field::<BucketingCfg>(super::RedisCfgKey_Bucketing.to_string());
+        dyn_cfg.add_typed_field::<BanditCfg>(super::RedisCfgKey_Bandit.to_string());
+        dyn_cfg.add_typed_field::<ShadowCfg>(super::RedisCfgKey_Shadow.to_string());
 
         // 启动定时任务
-        let monitor = Monitor::new(dyn_cfg.clone());
-        monitor.start();
+        let monitor = Monitor::new(dyn_cfg.clone(), sync_cron.to_string());
+        monitor.start().await;
 
         dyn_cfg
     }
@@ -52,6 +137,19 @@ impl DyncConfigV2 {
         self.add_field(key, CfgFieldField::Hash(BTreeMap::new()));
     }
 
+    /// 注册类型化配置, 不合法的写入会被拒绝并保留上一个合法值
+    pub fn add_typed_field<T: HashCfg>(&self, key: String) {
+        let value: AnyCfg = Arc::new(T::default());
+        self.add_field(
+            key,
+            CfgFieldField::Typed(TypedCfg {
+                raw: BTreeMap::new(),
+                value,
+                parse: parse_typed::<T>,
+            }),
+        );
+    }
+
     pub fn add_str_field(&self, key: String) {
         let mut fields = self.fields.write().unwrap();
         fields.insert(key, CfgFieldField::Str("".to_string()));
@@ -62,6 +160,7 @@ impl DyncConfigV2 {
         fields.insert(key, value);
     }
 
+    #[cfg(test)]
     pub fn get_string(&self, key: &str) -> String {
         let fields = self.fields.read().unwrap();
         match fields.get(key) {
@@ -70,6 +169,7 @@ impl DyncConfigV2 {
         }
     }
 
+    #[cfg(test)]
     pub fn get_i64(&self, key: &str) -> i64 {
         let fields = self.fields.read().unwrap();
         match fields.get(key) {
@@ -78,111 +178,276 @@ impl DyncConfigV2 {
         }
     }
 
-    pub fn get_f64(&self, key: &str) -> f64 {
+    pub fn get_hash(&self, key: &str) -> BTreeMap<String, String> {
         let fields = self.fields.read().unwrap();
         match fields.get(key) {
-            Some(CfgFieldField::Float64(val)) => *val,
-            _ => 0.0,
+            Some(CfgFieldField::Hash(val)) => val.clone(),
+            Some(CfgFieldField::Typed(val)) => val.raw.clone(),
+            _ => BTreeMap::default(),
         }
     }
 
-    pub fn get_hash(&self, key: &str) -> BTreeMap<String, String> {
+    /// 类型化配置的当前值, 未注册或类型不符时返回默认值
+    pub fn get_cfg<T: HashCfg>(&self, key: &str) -> T {
         let fields = self.fields.read().unwrap();
         match fields.get(key) {
-            Some(CfgFieldField::Hash(val)) => val.clone(),
-            _ => BTreeMap::default(),
+            Some(CfgFieldField::Typed(val)) => {
+                val.value.downcast_ref::<T>().cloned().unwrap_or_default()
+            }
+            _ => T::default(),
         }
     }
 
-    fn sync_redis(&mut self) {
-        let mut fields = self.fields.write().unwrap();
-
-        log::info!("DyncConfigV2 Monitor sync redis keys={}", fields.len());
-        for (key, val) in fields.iter_mut() {
-            match val {
-                CfgFieldField::Str(val) => {
-                    let v = self.redis_client.get(key).unwrap_or_default();
-                    if v != *val {
-                        log::info!(
-                            "sync redis Str key={} preval={:?}, newval={:?}",
-                            key,
-                            val,
-                            v
-                        );
-                    }
-                    *val = v;
+    /// 读取最新值, 类型化配置校验失败时返回None
+    async fn fetch(&self, key: &str, val: &CfgFieldField) -> anyhow::Result<Option<CfgFieldField>> {
+        let new_val = match val {
+            CfgFieldField::Str(_) => {
+                CfgFieldField::Str(self.storage.get(key).await?.unwrap_or_default())
+            }
+            CfgFieldField::Int64(_) => {
+                CfgFieldField::Int64(read_parse(self.storage.get(key).await?))
+            }
+            CfgFieldField::Hash(_) => CfgFieldField::Hash(self.storage.hgetall(key).await?),
+            CfgFieldField::Typed(typed) => {
+                let raw = self.storage.hgetall(key).await?;
+                if raw == typed.raw {
+                    return Ok(Some(val.clone()));
                 }
-                CfgFieldField::Int64(val) => {
-                    let v: i64 = self.redis_client.get(key).unwrap_or_default();
-                    if v != *val {
-                        log::info!(
-                            "sync redis Int64 key={} preval={:?}, newval={:?}",
-                            key,
-                            val,
-                            v
-                        );
+                match (typed.parse)(&raw) {
+                    Ok(value) => {
+                        self.rejected.write().unwrap().remove(key);
+                        CfgFieldField::Typed(TypedCfg {
+                            raw,
+                            value,
+                            parse: typed.parse,
+                        })
+                    }
+                    Err(e) => {
+                        self.reject(key, raw, e);
+                        return Ok(None);
                     }
-                    *val = v;
                 }
-                CfgFieldField::Hash(val) => {
-                    let v: BTreeMap<String, String> =
-                        self.redis_client.hgetall(key).unwrap_or_default();
-                    let old = format!("{:?}", *val);
-                    let new = format!("{:?}", v);
+            }
+        };
+        Ok(Some(new_val))
+    }
 
+    fn reject(&self, key: &str, raw: BTreeMap<String, String>, err: anyhow::Error) {
+        let mut rejected = self.rejected.write().unwrap();
+        if rejected.get(key) == Some(&raw) {
+            return;
+        }
+        log::error!(
+            "DyncConfigV2 rejected key={} val={:?}, keep last known good: {}",
+            key,
+            raw,
+            err
+        );
+        metrics::increment_counter!("dyn_cfg_rejected_total", "key" => key.to_string());
+        rejected.insert(key.to_string(), raw);
+    }
+
+    /// 从redis读取所有注册的key, 不持有字段锁等待IO
+    async fn sync_redis(&self) {
+        let _sync = self.sync_lock.lock().await;
+        let snapshot: Vec<(String, CfgFieldField)> = {
+            let fields = self.fields.read().unwrap();
+            fields.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+        };
+
+        log::info!("DyncConfigV2 Monitor sync redis keys={}", snapshot.len());
+        let mut updates = Vec::with_capacity(snapshot.len());
+        for (key, val) in snapshot {
+            let new_val = match self.fetch(&key, &val).await {
+                Ok(Some(new_val)) => new_val,
+                Ok(None) => continue,
+                Err(e) => {
+                    log::error!("DyncConfigV2 sync redis key={} error: {}", key, e);
+                    continue;
+                }
+            };
+            updates.push((key, new_val));
+        }
+        self.apply(updates);
+    }
+
+    /// 只重新加载一个key, 未注册的key忽略
+    pub(crate) async fn sync_key(&self, key: &str) {
+        let _sync = self.sync_lock.lock().await;
+        let val = self.fields.read().unwrap().get(key).cloned();
+        let val = match val {
+            Some(val) => val,
+            None => return,
+        };
+        match self.fetch(key, &val).await {
+            Ok(Some(new_val)) => self.apply(vec![(key.to_string(), new_val)]),
+            Ok(None) => {}
+            Err(e) => log::error!("DyncConfigV2 sync redis key={} error: {}", key, e),
+        }
+    }
+
+    fn apply(&self, updates: Vec<(String, CfgFieldField)>) {
+        let mut changes = Vec::new();
+        {
+            let mut fields = self.fields.write().unwrap();
+            for (key, new_val) in updates {
+                if let Some(val) = fields.get_mut(&key) {
+                    let old = format!("{:?}", val);
+                    let new = format!("{:?}", new_val);
                     if old != new {
-                        log::info!(
-                            "sync redis Hash key={} preval={:?}, newval={:?}",
+                        log::info!("sync redis key={} preval={}, newval={}", key, old, new);
+                        changes.push(CfgChange {
                             key,
-                            val,
-                            v
-                        );
+                            old: val.render(),
+                            new: new_val.render(),
+                            timestamp: Local::now(),
+                        });
                     }
-                    *val = v;
+                    *val = new_val;
                 }
-                CfgFieldField::Float64(val) => {
-                    let v: f64 = self.redis_client.get(key).unwrap_or_default();
-                    if v != *val {
-                        log::info!(
-                            "sync redis Float64 key={} preval={:?}, newval={:?}",
-                            key,
-                            val,
-                            v
-                        );
-                    }
-                    *val = v;
+            }
+        }
+        if changes.is_empty() {
+            return;
+        }
+
+        {
+            let mut history = self.changes.lock().unwrap();
+            for change in &changes {
+                if history.len() >= CFG_CHANGE_HISTORY {
+                    history.pop_front();
                 }
+                history.push_back(change.clone());
+            }
+        }
+
+        // 不持有锁调用回调, 回调里可以直接读取新配置
+        for change in &changes {
+            let listeners = self
+                .listeners
+                .read()
+                .unwrap()
+                .get(&change.key)
+                .cloned()
+                .unwrap_or_default();
+            for listener in listeners {
+                listener(change);
             }
         }
     }
+
+    /// 注册key变更回调, 在配置生效后调用
+    pub fn on_change<F>(&self, key: &str, listener: F)
+    where
+        F: Fn(&CfgChange) + Send + Sync + 'static,
+    {
+        let mut listeners = self.listeners.write().unwrap();
+        listeners
+            .entry(key.to_string())
+            .or_default()
+            .push(Arc::new(listener));
+    }
+
+    /// 最近的配置变更, 最新的在前, key为None时返回所有key
+    pub fn recent_changes(&self, key: Option<&str>, limit: usize) -> Vec<CfgChange> {
+        let history = self.changes.lock().unwrap();
+        history
+            .iter()
+            .rev()
+            .filter(|c| key.is_none_or(|k| c.key == k))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    /// 订阅配置变更通知, 收到后立即重新加载对应key; 订阅重建时全量同步一次
+    async fn watch(&self) {
+        let mut rx = match self
+            .storage
+            .subscribe(
+                &[super::RedisChannel_CfgChanged],
+                &[super::RedisPattern_CfgKeyspace],
+            )
+            .await
+        {
+            Ok(rx) => rx,
+            Err(e) => {
+                log::error!("DyncConfigV2 subscribe error, polling only: {}", e);
+                return;
+            }
+        };
+
+        let cfg = self.clone();
+        tokio::spawn(async move {
+            while let Some(notification) = rx.recv().await {
+                match notification {
+                    Notification::Subscribed => cfg.sync_redis().await,
+                    Notification::Message { channel, payload } => {
+                        if let Some(key) = changed_key(&channel, &payload) {
+                            metrics::increment_counter!("dyn_cfg_notify_total");
+                            log::debug!("DyncConfigV2 change notified key={}", key);
+                            cfg.sync_key(key).await;
+                        }
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// 从变更通知中取出配置key, 支持主动发布和keyspace通知两种来源;
+/// leader选举的key在每次续约时都会变化, 不触发重新加载
+fn changed_key<'a>(channel: &'a str, payload: &'a str) -> Option<&'a str> {
+    let key = if channel == super::RedisChannel_CfgChanged {
+        payload
+    } else {
+        channel.split_once("__:").map(|(_, key)| key)?
+    };
+    match key {
+        super::RedisCfgKey_MasterServer | super::RedisCfgKey_MasterToken => None,
+        key => Some(key),
+    }
+}
+
+fn read_parse<T>(s: Option<String>) -> T
+where
+    T: str::FromStr + Default,
+{
+    s.map_or_else(T::default, |s| s.parse().unwrap_or_default())
 }
 
 struct Monitor {
     scheduler: JobScheduler,
     dync_cfg: DyncConfigV2,
+    cron: String,
 }
 
 impl Monitor {
-    pub fn new(dync_cfg: DyncConfigV2) -> Self {
+    pub fn new(dync_cfg: DyncConfigV2, cron: String) -> Self {
         let sched = JobScheduler::new().unwrap();
 
         Self {
             scheduler: sched,
-            dync_cfg: dync_cfg,
+            dync_cfg,
+            cron,
         }
     }
 
-    pub fn start(&self) {
-        log::info!("starting dyn config monitor");
-        let mut cfg = self.dync_cfg.clone();
-
-        let _ = self
-            .scheduler
-            .add(Job::new("*/10 * * * * *", move |_, _| cfg.sync_redis()).unwrap());
+    pub async fn start(&self) {
+        log::info!("starting dyn config monitor, cron={}", self.cron);
+        let cfg = self.dync_cfg.clone();
+
+        let _ = self.scheduler.add(
+            Job::new_async(self.cron.as_str(), move |_, _| {
+                let cfg = cfg.clone();
+                Box::pin(async move { cfg.sync_redis().await })
+            })
+            .unwrap(),
+        );
         self.scheduler.start().unwrap();
 
-        let mut cfg = self.dync_cfg.clone();
-        cfg.sync_redis();
+        self.dync_cfg.sync_redis().await;
+        self.dync_cfg.watch().await;
     }
 }
 
@@ -190,13 +455,13 @@ impl Monitor {
 mod tests {
 
     use super::*;
+    use crate::dao::{MemoryStorage, Storage};
 
     #[test]
     fn test_hash() {
         let rt = tokio::runtime::Runtime::new().unwrap();
         rt.block_on(async {
-            let redis_client = redis::Client::open("redis://127.0.0.1:6379/").unwrap();
-            let cfg = DyncConfigV2::new(redis_client);
+            let cfg = DyncConfigV2::new(Arc::new(MemoryStorage::new())).await;
             let hash = cfg.get_hash("hash_key1");
             println!("{:?}", hash);
 
@@ -216,8 +481,7 @@ mod tests {
     fn get_string() {
         let rt = tokio::runtime::Runtime::new().unwrap();
         rt.block_on(async {
-            let redis_client = redis::Client::open("redis://127.0.0.1:6379/").unwrap();
-            let cfg = DyncConfigV2::new(redis_client);
+            let cfg = DyncConfigV2::new(Arc::new(MemoryStorage::new())).await;
 
             let val1 = cfg.get_string("key1");
             println!("val1={}", val1);
@@ -232,8 +496,7 @@ mod tests {
     fn get_i32() {
         let rt = tokio::runtime::Runtime::new().unwrap();
         rt.block_on(async {
-            let redis_client = redis::Client::open("redis://127.0.0.1:6379/").unwrap();
-            let cfg = DyncConfigV2::new(redis_client);
+            let cfg = DyncConfigV2::new(Arc::new(MemoryStorage::new())).await;
             let val1 = cfg.get_i64("key1");
             println!("val1={}", val1);
 
@@ -242,4 +505,134 @@ mod tests {
             println!("val2={}", val1);
         });
     }
+
+    #[test]
+    fn test_changed_key() {
+        assert_eq!(
+            changed_key("cfg:changed", "cfg:exp:base"),
+            Some("cfg:exp:base")
+        );
+        assert_eq!(
+            changed_key("__keyspace@0__:cfg:exp:ab", "hset"),
+            Some("cfg:exp:ab")
+        );
+        assert_eq!(changed_key("other", "x"), None);
+        assert_eq!(changed_key("__keyspace@0__:cfg:master", "pexpire"), None);
+        assert_eq!(
+            changed_key("__keyspace@0__:cfg:master:token", "incrby"),
+            None
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_apply_on_notify() {
+        let storage = Arc::new(MemoryStorage::new());
+        // 轮询每年一次, 只能靠通知生效
+        let cfg = DyncConfigV2::new_with_cron(storage.clone(), "0 0 0 1 1 *").await;
+        assert!(cfg.get_hash("cfg:exp:base").is_empty());
+
+        storage
+            .hset_multiple(
+                "cfg:exp:base",
+                &[
+                    ("version".to_string(), "2".to_string()),
+                    (
+                        "start_time".to_string(),
+                        "2022-05-30 10:27:47+0800".to_string(),
+                    ),
+                ],
+            )
+            .await
+            .unwrap();
+        storage
+            .publish("cfg:changed", "cfg:exp:base")
+            .await
+            .unwrap();
+
+        for _ in 0..100 {
+            if !cfg.get_hash("cfg:exp:base").is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(cfg.get_hash("cfg:exp:base").get("version").unwrap(), "2");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_reject_invalid_typed_cfg() {
+        let storage = Arc::new(MemoryStorage::new());
+        storage
+            .hset_multiple(
+                "cfg:exp:driver",
+                &[("min_requests".to_string(), "500".to_string())],
+            )
+            .await
+            .unwrap();
+        let cfg = DyncConfigV2::new_with_cron(storage.clone(), "0 0 0 1 1 *").await;
+        assert_eq!(
+            cfg.get_cfg::<ExpDriverCfg>("cfg:exp:driver").min_requests,
+            500
+        );
+
+        storage
+            .hset_multiple(
+                "cfg:exp:driver",
+                &[("min_requests".to_string(), "lots".to_string())],
+            )
+            .await
+            .unwrap();
+        cfg.sync_redis().await;
+        // 保留上一个合法值
+        assert_eq!(
+            cfg.get_cfg::<ExpDriverCfg>("cfg:exp:driver").min_requests,
+            500
+        );
+        assert!(cfg.rejected.read().unwrap().contains_key("cfg:exp:driver"));
+
+        storage
+            .hset_multiple(
+                "cfg:exp:driver",
+                &[("min_requests".to_string(), "800".to_string())],
+            )
+            .await
+            .unwrap();
+        cfg.sync_key("cfg:exp:driver").await;
+        assert_eq!(
+            cfg.get_cfg::<ExpDriverCfg>("cfg:exp:driver").min_requests,
+            800
+        );
+        assert!(cfg.rejected.read().unwrap().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_change_listener_and_history() {
+        let storage = Arc::new(MemoryStorage::new());
+        let cfg = DyncConfigV2::new_with_cron(storage.clone(), "0 0 0 1 1 *").await;
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let reader = cfg.clone();
+        let seen_clone = seen.clone();
+        cfg.on_change("cfg:mainaction:rate", move |change| {
+            // 回调内可以读到新值
+            assert_eq!(reader.get_i64(&change.key).to_string(), change.new);
+            seen_clone.lock().unwrap().push(change.clone());
+        });
+
+        storage.set("cfg:mainaction:rate", "3", None).await.unwrap();
+        cfg.sync_key("cfg:mainaction:rate").await;
+        storage.set("cfg:mainaction:rate", "5", None).await.unwrap();
+        cfg.sync_redis().await;
+        // 值未变化时不产生变更
+        cfg.sync_redis().await;
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 2);
+        assert_eq!((seen[0].old.as_str(), seen[0].new.as_str()), ("0", "3"));
+        assert_eq!((seen[1].old.as_str(), seen[1].new.as_str()), ("3", "5"));
+
+        let changes = cfg.recent_changes(Some("cfg:mainaction:rate"), 1);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].new, "5");
+        assert_eq!(cfg.recent_changes(Some("cfg:exp:base"), 10).len(), 0);
+    }
 }
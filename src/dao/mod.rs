@@ -1,10 +1,15 @@
 pub mod ads_dao;
+pub mod cfg_schema;
 pub mod dyn_cfg;
 pub mod redis_dao;
+pub mod signal_table;
+pub mod storage;
 
 pub use ads_dao::*;
 pub use dyn_cfg::*;
 pub use redis_dao::*;
+pub use signal_table::*;
+pub use storage::*;
 
 const RedisCfgKey_ExpSignalDailyTotalTemptClick: &str = "cfg:signal:tempclick"; //
 const RedisCfgKey_ExpSignalAdIdFillRate: &str = "cfg:signal:adid:fillrate"; //
@@ -12,13 +17,23 @@ const RedisCfgKey_ExpSignalAdIdShowRate: &str = "cfg:signal:adid:showrate"; //
 const RedisCfgKey_ExpSignalAdIdClickRate: &str = "cfg:signal:adid:clickrate"; //
 const RedisCfgKey_ExpTargetCtrAction: &str = "cfg:exp:action:targetctr:{}"; //
 
-const RedisKey_ExpAdidDefalutChoice: &str = "exp:default:adid:choices"; //  默认选择的广告id
 const RedisKey_ExpVersionAdids: &str = "expversion:adidlist:{}"; // 各版本的广告id列表
 const RedisCfgKey_ExpVersionAdIdCfg: &str = "expversion:cfg:{}:{}"; // 各版本的广告id配置列表
 const RedisCfgKey_ExpVersionAdIdScores: &str = "expversion:score:{}:{}"; // 各版本的广告id分数列表
 
-const RedisCfgKey_MasterServer: &str = "cfg:master"; //
+pub(crate) const RedisCfgKey_MasterServer: &str = "cfg:master"; // 当前leader, 值为 节点id#fencing token
+pub(crate) const RedisCfgKey_MasterToken: &str = "cfg:master:token"; // leader fencing token 自增序列
 const RedisCfgKey_AdidWhitelist: &str = "cfg:whitelist"; //
 const RedisCfgKey_MainActionRate: &str = "cfg:mainaction:rate"; //
 const RedisCfgKey_ExpBaseCfg: &str = "cfg:exp:base";
 const RedisCfgKey_ExpExpAbParams: &str = "cfg:exp:ab"; //
+const RedisCfgKey_ExpDriverCfg: &str = "cfg:exp:driver"; // 实验自动评估配置
+
+const RedisChannel_CfgChanged: &str = "cfg:changed"; // 配置变更通知, 消息内容为变更的key
+const RedisPattern_CfgKeyspace: &str = "__keyspace@*__:cfg:*"; // 需开启 notify-keyspace-events
+const RedisCfgKey_StrategyDefault: &str = "cfg:strategy:default"; // service_type => 默认策略名
+const RedisCfgKey_Shadow: &str = "cfg:shadow"; // 影子策略配置
+const RedisCfgKey_Bandit: &str = "cfg:bandit"; // bandit策略参数
+const RedisCfgKey_Bucketing: &str = "cfg:bucketing"; // 按实验版本的分桶与流量分配
+const RedisCfgKey_CtrControl: &str = "cfg:ctr:control"; // 目标CTR调整配置
+const RedisCfgKey_PredictCfg: &str = "cfg:predict"; // 预估配置, 如抽样方式
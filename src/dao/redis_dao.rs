@@ -1,104 +1,165 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use crate::model::*;
 use anyhow::{Ok, Result};
-use redis::{self, Commands};
+
+use super::{EventIncr, StorageRef};
 
 const CFG_EXPIRE_TIME: usize = 5 * 3600 * 24;
 
+pub(crate) fn adid_exp_cfg_key(version: &str, ad_id: i64) -> String {
+    format!("expversion:cfg:{}:{}", version, ad_id)
+}
+
+pub(crate) fn target_ctr_actions_key(ad_id: i64) -> String {
+    format!("cfg:exp:action:targetctr:{}", ad_id)
+}
+
 #[derive(Clone)]
 pub struct RedisDao {
-    pub redis_client: redis::Client,
+    pub storage: StorageRef,
 }
 
 impl RedisDao {
-    pub(crate) fn new(redis_client: redis::Client) -> RedisDao {
-        RedisDao {
-            redis_client: redis_client,
-        }
+    pub(crate) fn new(storage: StorageRef) -> RedisDao {
+        RedisDao { storage }
+    }
+
+    pub(crate) async fn incr_event_counters(&self, incrs: &[EventIncr]) -> Result<()> {
+        self.storage.incr_event_counters(incrs).await
     }
 
-    pub(crate) fn set_adid_exp_cfg(
+    pub(crate) async fn set_adid_exp_cfg(
         &self,
         version: &str,
         ad_id: i64,
         cfg: &crate::model::AdIdExpCfg,
     ) -> Result<()> {
-        let key = format!("expversion:cfg:{}:{}", version, ad_id);
+        let key = adid_exp_cfg_key(version, ad_id);
         let value = serde_json::to_string(cfg)?;
-        let mut conn = self.redis_client.get_connection()?;
-        let _: () = conn.set_ex(key, value, CFG_EXPIRE_TIME)?;
-        Ok(())
+        self.storage.set(&key, &value, Some(CFG_EXPIRE_TIME)).await
     }
 
-    pub(crate) fn get_adid_exp_cfg(&self, version: &str, ad_id: i64) -> Result<AdIdExpCfg> {
-        let key = format!("expversion:cfg:{}:{}", version, ad_id);
-        let mut conn = self.redis_client.get_connection()?;
-        let cfg_json: String = conn.get(key)?;
+    pub(crate) async fn get_adid_exp_cfg(&self, version: &str, ad_id: i64) -> Result<AdIdExpCfg> {
+        let key = adid_exp_cfg_key(version, ad_id);
+        let cfg_json = self
+            .storage
+            .get(&key)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("key={} not found", key))?;
         let cfg: AdIdExpCfg = serde_json::from_str(cfg_json.as_str())?;
 
         Ok(cfg)
     }
 
-    pub(crate) fn get_multi_event_by_keys(&self, _keys: Vec<&str>) -> Result<Vec<String>> {
-        todo!()
+    /// MGET批量读取, 不存在的key返回空串
+    pub(crate) async fn get_multi_event_by_keys(&self, keys: Vec<&str>) -> Result<Vec<String>> {
+        let values = self.storage.mget(&keys).await?;
+        Ok(values.into_iter().map(|v| v.unwrap_or_default()).collect())
     }
 
-    pub(crate) fn get_ad_exp_action_score(
-        &self,
-        version: &str,
-        ad_id: i64,
-    ) -> Result<HashMap<String, i64>> {
-        let mut conn = self.redis_client.get_connection()?;
-        let key = format!("expversion:score:{}:{}", version, ad_id);
-        let action_scores: HashMap<String, i64> = conn.hgetall(key)?;
-        Ok(action_scores)
+    pub(crate) async fn get_counter(&self, key: &str) -> Result<i64> {
+        let count = self.storage.get(key).await?;
+        Ok(count.map_or(0, |c| c.parse().unwrap_or_default()))
     }
 
-    pub(crate) fn set_ad_exp_action_score(
+    pub(crate) async fn set_ad_exp_action_score(
         &self,
         version: &str,
         ad_id: i64,
         scores: HashMap<String, i64>,
     ) -> Result<()> {
-        let mut conn = self.redis_client.get_connection()?;
         let key = format!("expversion:score:{}:{}", version, ad_id);
-        for kv in scores {
-            let _: () = conn.hset(&key, kv.0, kv.1)?;
+        let values: Vec<(String, String)> = scores
+            .into_iter()
+            .map(|(k, v)| (k, v.to_string()))
+            .collect();
+        self.storage.hset_multiple(&key, &values).await
+    }
+
+    /// 只有lease仍是当前leader租约时才写入, 返回是否写入
+    pub(crate) async fn update_exp_base_cfg(&self, cfg: &ExpBaseCfg, lease: &str) -> Result<bool> {
+        let now = chrono::Local::now();
+        let start_time = now.format("%Y-%m-%d %H:%M:%S%z").to_string();
+        let values = [
+            ("version".to_string(), cfg.version.clone()),
+            ("start_time".to_string(), start_time),
+        ];
+        let written = self
+            .storage
+            .hset_multiple_if_eq(
+                super::RedisCfgKey_MasterServer,
+                lease,
+                super::RedisCfgKey_ExpBaseCfg,
+                &values,
+            )
+            .await?;
+        if written {
+            self.notify_cfg_changed(super::RedisCfgKey_ExpBaseCfg)
+                .await?;
         }
-        Ok(())
+        Ok(written)
     }
 
-    pub(crate) fn get_exp_adid_default_choice(
-        &self,
-        _version: &str,
-        _ad_id: i64,
-    ) -> Result<HashMap<String, i64>> {
-        let mut conn = self.redis_client.get_connection()?;
-        let default_choice: HashMap<String, i64> =
-            conn.hgetall(super::RedisKey_ExpAdidDefalutChoice)?;
-        Ok(default_choice)
+    /// 通知所有节点立即重新加载配置key
+    pub(crate) async fn notify_cfg_changed(&self, key: &str) -> Result<()> {
+        self.storage
+            .publish(super::RedisChannel_CfgChanged, key)
+            .await
     }
 
-    pub(crate) fn update_exp_base_cfg(&self, cfg: &ExpBaseCfg) -> Result<()> {
-        let mut conn = self.redis_client.get_connection()?;
-        let now = chrono::Local::now();
-        let start_time = now.format("%Y-%m-%d %H:%M:%S%z").to_string();
-        let values = [("version", &cfg.version), ("start_time", &start_time)];
-        conn.hset_multiple(super::RedisCfgKey_ExpBaseCfg, &values)?;
-        Ok(())
+    pub(crate) async fn update_adids(&self, version: &str, ad_ids: Vec<i64>) -> Result<()> {
+        let key = format!("expversion:adidlist:{}", version);
+        let values: Vec<(String, String)> = ad_ids
+            .iter()
+            .map(|ad_id| (ad_id.to_string(), "1".to_string()))
+            .collect();
+        self.storage.hset_multiple(&key, &values).await?;
+        self.storage.expire(&key, CFG_EXPIRE_TIME).await
+    }
+
+    pub(crate) async fn get_adids(&self, version: &str) -> Result<Vec<i64>> {
+        let key = format!("expversion:adidlist:{}", version);
+        let ad_ids = self.storage.hgetall(&key).await?;
+        Ok(ad_ids.keys().filter_map(|k| k.parse().ok()).collect())
     }
 
-    pub(crate) fn update_adids(&self, _version: &str, _ad_ids: Vec<i64>) -> Result<()> {
-        todo!()
+    /// 广告可选的目标CTR动作: action_id -> 目标CTR
+    pub(crate) async fn get_target_ctr_actions(&self, ad_id: i64) -> Result<BTreeMap<String, f64>> {
+        let actions = self.storage.hgetall(&target_ctr_actions_key(ad_id)).await?;
+        Ok(parse_f64_hash(actions))
     }
+
+    /// 批量读取多个广告的目标CTR动作, 一次pipeline, 结果与ad_ids一一对应
+    pub(crate) async fn get_multi_target_ctr_actions(
+        &self,
+        ad_ids: &[i64],
+    ) -> Result<Vec<BTreeMap<String, f64>>> {
+        let keys: Vec<String> = ad_ids
+            .iter()
+            .map(|id| target_ctr_actions_key(*id))
+            .collect();
+        let keys: Vec<&str> = keys.iter().map(|k| k.as_str()).collect();
+        let hashes = self.storage.hgetall_multi(&keys).await?;
+        Ok(hashes.into_iter().map(parse_f64_hash).collect())
+    }
+}
+
+fn parse_f64_hash(values: BTreeMap<String, String>) -> BTreeMap<String, f64> {
+    values
+        .into_iter()
+        .filter_map(|(k, v)| v.parse().ok().map(|v| (k, v)))
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
     use chrono::{DateTime, Local};
 
     use super::*;
+    use crate::dao::MemoryStorage;
 
     #[test]
     fn test_datetime_format() {
@@ -112,15 +173,73 @@ mod tests {
 
         println!("{}", date);
     }
-    #[test]
-    fn test_update_exp_base_cfg() {
-        let redis_client = redis::Client::open("redis://127.0.0.1:6379/").unwrap();
-        let redis_dao = RedisDao::new(redis_client);
-        redis_dao.update_exp_base_cfg(&ExpBaseCfg {
+    #[tokio::test]
+    async fn test_update_exp_base_cfg() {
+        let redis_dao = RedisDao::new(Arc::new(MemoryStorage::new()));
+        let cfg = ExpBaseCfg {
             version: "1.0.0".to_string(),
             base_value: 0.0,
             score_factor: 0.0,
             start_time: chrono::Local::now(),
-        });
+        };
+        redis_dao
+            .storage
+            .set(crate::dao::RedisCfgKey_MasterServer, "a#1", None)
+            .await
+            .unwrap();
+        // 不是当前租约时拒绝写入
+        assert!(!redis_dao.update_exp_base_cfg(&cfg, "b#2").await.unwrap());
+        assert!(redis_dao
+            .storage
+            .hgetall(crate::dao::RedisCfgKey_ExpBaseCfg)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(redis_dao.update_exp_base_cfg(&cfg, "a#1").await.unwrap());
+
+        let cfg = redis_dao
+            .storage
+            .hgetall(crate::dao::RedisCfgKey_ExpBaseCfg)
+            .await
+            .unwrap();
+        assert_eq!(cfg.get("version").unwrap(), "1.0.0");
+    }
+
+    #[tokio::test]
+    async fn test_update_adids() {
+        let redis_dao = RedisDao::new(Arc::new(MemoryStorage::new()));
+        redis_dao.update_adids("1", vec![3, 1]).await.unwrap();
+        redis_dao.update_adids("1", vec![2, 3]).await.unwrap();
+        let mut ad_ids = redis_dao.get_adids("1").await.unwrap();
+        ad_ids.sort();
+        assert_eq!(ad_ids, vec![1, 2, 3]);
+        assert!(redis_dao.get_adids("2").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_adid_exp_cfg_and_scores() {
+        let redis_dao = RedisDao::new(Arc::new(MemoryStorage::new()));
+        assert!(redis_dao.get_adid_exp_cfg("1", 12).await.is_err());
+
+        let cfg = AdIdExpCfg {
+            ad_id: 12,
+            version: "1".to_string(),
+            ..Default::default()
+        };
+        redis_dao.set_adid_exp_cfg("1", 12, &cfg).await.unwrap();
+        assert_eq!(redis_dao.get_adid_exp_cfg("1", 12).await.unwrap().ad_id, 12);
+
+        let scores = HashMap::from([("a".to_string(), 3), ("b".to_string(), 5)]);
+        redis_dao
+            .set_ad_exp_action_score("1", 12, scores)
+            .await
+            .unwrap();
+        let stored = redis_dao
+            .storage
+            .hgetall("expversion:score:1:12")
+            .await
+            .unwrap();
+        assert_eq!(stored.get("a").unwrap(), "3");
+        assert_eq!(stored.get("b").unwrap(), "5");
     }
 }
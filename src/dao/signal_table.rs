@@ -0,0 +1,165 @@
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+
+use super::DyncConfigV2;
+use crate::model::RangeTable;
+
+/// 四张信号表, 配置变化时重新解析后整体替换, 读取无锁且不复制表
+#[derive(Default)]
+pub struct SignalTables {
+    tempt_click: ArcSwap<RangeTable>,
+    fill_rate: ArcSwap<RangeTable>,
+    show_rate: ArcSwap<RangeTable>,
+    click_rate: ArcSwap<RangeTable>,
+}
+
+impl SignalTables {
+    /// 加载当前配置并注册变更回调
+    pub fn watch(dyn_cfg: &DyncConfigV2) -> Arc<Self> {
+        let tables = Arc::new(Self::default());
+        for key in [
+            super::RedisCfgKey_ExpSignalDailyTotalTemptClick,
+            super::RedisCfgKey_ExpSignalAdIdFillRate,
+            super::RedisCfgKey_ExpSignalAdIdShowRate,
+            super::RedisCfgKey_ExpSignalAdIdClickRate,
+        ] {
+            let reader = dyn_cfg.clone();
+            let weak = Arc::downgrade(&tables);
+            dyn_cfg.on_change(key, move |change| {
+                if let Some(tables) = weak.upgrade() {
+                    tables.reload(&change.key, &reader);
+                }
+            });
+            tables.reload(key, dyn_cfg);
+        }
+        tables
+    }
+
+    fn slot(&self, key: &str) -> Option<&ArcSwap<RangeTable>> {
+        match key {
+            super::RedisCfgKey_ExpSignalDailyTotalTemptClick => Some(&self.tempt_click),
+            super::RedisCfgKey_ExpSignalAdIdFillRate => Some(&self.fill_rate),
+            super::RedisCfgKey_ExpSignalAdIdShowRate => Some(&self.show_rate),
+            super::RedisCfgKey_ExpSignalAdIdClickRate => Some(&self.click_rate),
+            _ => None,
+        }
+    }
+
+    /// 重新解析, 不合法的项跳过并记录warn日志
+    fn reload(&self, key: &str, dyn_cfg: &DyncConfigV2) {
+        let slot = match self.slot(key) {
+            Some(slot) => slot,
+            None => return,
+        };
+        let (table, skipped) = RangeTable::parse(&dyn_cfg.get_hash(key));
+        for reason in &skipped {
+            log::warn!("signal table {} skip {}", key, reason);
+        }
+        if !skipped.is_empty() {
+            metrics::counter!("signal_table_skipped_total", skipped.len() as u64, "key" => key.to_string());
+        }
+        if !table.gaps().is_empty() {
+            log::warn!("signal table {} has gaps {:?}", key, table.gaps());
+        }
+        log::info!(
+            "signal table {} reloaded, ranges={}",
+            key,
+            table.ranges().len()
+        );
+        slot.store(Arc::new(table));
+    }
+
+    pub fn tempt_click(&self) -> Arc<RangeTable> {
+        self.tempt_click.load_full()
+    }
+
+    pub fn fill_rate(&self) -> Arc<RangeTable> {
+        self.fill_rate.load_full()
+    }
+
+    pub fn show_rate(&self) -> Arc<RangeTable> {
+        self.show_rate.load_full()
+    }
+
+    pub fn click_rate(&self) -> Arc<RangeTable> {
+        self.click_rate.load_full()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dao::{MemoryStorage, Storage};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_signal_tables_reload_on_change() {
+        let storage = Arc::new(MemoryStorage::new());
+        storage
+            .hset_multiple(
+                "cfg:signal:adid:fillrate",
+                &[("0_1".to_string(), "2".to_string())],
+            )
+            .await
+            .unwrap();
+        let dyn_cfg = DyncConfigV2::new_with_cron(storage.clone(), "0 0 0 1 1 *").await;
+        let tables = SignalTables::watch(&dyn_cfg);
+        assert_eq!(tables.fill_rate().lookup(0.5), 2.0);
+
+        let before = tables.fill_rate.load_full();
+        storage
+            .hset_multiple(
+                "cfg:signal:adid:fillrate",
+                &[("0_1".to_string(), "4".to_string())],
+            )
+            .await
+            .unwrap();
+        storage
+            .publish("cfg:changed", "cfg:signal:adid:fillrate")
+            .await
+            .unwrap();
+        for _ in 0..100 {
+            if tables.fill_rate().lookup(0.5) == 4.0 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(tables.fill_rate().lookup(0.5), 4.0);
+        // 已取出的旧表不受影响
+        assert_eq!(before.lookup(0.5), 2.0);
+
+        // 与已有区间重叠的区间被跳过, 其余区间照常生效
+        storage
+            .hset_multiple(
+                "cfg:signal:adid:fillrate",
+                &[("0.5_2".to_string(), "8".to_string())],
+            )
+            .await
+            .unwrap();
+        dyn_cfg.sync_key("cfg:signal:adid:fillrate").await;
+        assert_eq!(tables.fill_rate().lookup(0.5), 4.0);
+        assert_eq!(tables.fill_rate().lookup(1.5), 0.0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_signal_tables_startup_with_bad_entries() {
+        let storage = Arc::new(MemoryStorage::new());
+        storage
+            .hset_multiple(
+                "cfg:signal:adid:showrate",
+                &[
+                    ("0_1".to_string(), "2".to_string()),
+                    ("0.5_2".to_string(), "8".to_string()),
+                    ("1_x".to_string(), "3".to_string()),
+                    ("comment".to_string(), "legacy".to_string()),
+                ],
+            )
+            .await
+            .unwrap();
+        let dyn_cfg = DyncConfigV2::new_with_cron(storage.clone(), "0 0 0 1 1 *").await;
+        let tables = SignalTables::watch(&dyn_cfg);
+        // 启动时不合法的项被跳过, 合法的区间照常生效
+        assert_eq!(tables.show_rate().ranges().len(), 1);
+        assert_eq!(tables.show_rate().lookup(0.5), 2.0);
+    }
+}
@@ -0,0 +1,860 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use bb8_redis::{bb8, RedisConnectionManager};
+use futures_util::StreamExt;
+use redis::AsyncCommands;
+use tokio::sync::mpsc;
+
+/// 原子地对 `request_fill_show_click` 编码的计数加一
+/// KEYS: 计数key列表, ARGV: 每个key依次对应 (字段下标, 过期秒数, 0表示不过期)
+/// 字段下标为0时按普通整数计数 INCR; 一次调用包含实时窗口、每日、实验分组等不同前缀的key,
+/// 在Redis Cluster上会因跨slot失败 (CROSSSLOT)
+const INCR_EVENT_SCRIPT: &str = r#"
+for i, key in ipairs(KEYS) do
+    local idx = tonumber(ARGV[i * 2 - 1])
+    local ttl = tonumber(ARGV[i * 2])
+    if idx == 0 then
+        redis.call('INCR', key)
+        if ttl > 0 then
+            redis.call('EXPIRE', key, ttl)
+        end
+    else
+        local counts = {0, 0, 0, 0}
+        local cur = redis.call('GET', key)
+        if cur then
+            local n = 1
+            for v in string.gmatch(cur, '[^_]+') do
+                if n <= 4 then
+                    counts[n] = tonumber(v) or 0
+                end
+                n = n + 1
+            end
+        end
+        counts[idx] = counts[idx] + 1
+        if ttl > 0 then
+            redis.call('SET', key, table.concat(counts, '_'), 'EX', ttl)
+        else
+            redis.call('SET', key, table.concat(counts, '_'))
+        end
+    end
+end
+return #KEYS
+"#;
+
+/// 值相等时才续期, 用于租约续约
+const PEXPIRE_IF_EQ_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('PEXPIRE', KEYS[1], ARGV[2])
+end
+return 0
+"#;
+
+/// KEYS[1]的值等于ARGV[1]时才写入KEYS[2]的hash, 用于leader的fencing写入
+/// ARGV[2..]: field, value 依次排列
+const HSET_IF_EQ_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) ~= ARGV[1] then
+    return 0
+end
+for i = 2, #ARGV, 2 do
+    redis.call('HSET', KEYS[2], ARGV[i], ARGV[i + 1])
+end
+return 1
+"#;
+
+/// 值相等时才删除, 用于释放租约
+const DEL_IF_EQ_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('DEL', KEYS[1])
+end
+return 0
+"#;
+
+/// 一次计数: key, 字段下标(0..4, None表示普通整数计数), 过期秒数(0表示不过期)
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventIncr {
+    pub key: String,
+    pub field: Option<usize>,
+    pub ttl: usize,
+}
+
+/// 订阅收到的通知
+#[derive(Debug, Clone, PartialEq)]
+pub enum Notification {
+    Message {
+        channel: String,
+        payload: String,
+    },
+    /// 订阅(重新)建立, 断开期间的消息可能已丢失
+    Subscribed,
+}
+
+/// 存储抽象, 覆盖 RedisDao 与 DyncConfigV2 用到的所有操作
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn get(&self, key: &str) -> Result<Option<String>>;
+
+    /// 结果与keys一一对应, 不存在的key为None
+    async fn mget(&self, keys: &[&str]) -> Result<Vec<Option<String>>>;
+
+    /// ttl为None时不过期
+    async fn set(&self, key: &str, value: &str, ttl: Option<usize>) -> Result<()>;
+
+    async fn incr_by(&self, key: &str, delta: i64) -> Result<i64>;
+
+    async fn expire(&self, key: &str, ttl: usize) -> Result<()>;
+
+    async fn hgetall(&self, key: &str) -> Result<BTreeMap<String, String>>;
+
+    /// 一次pipeline读取多个hash, 结果与keys一一对应, 不存在的key为空
+    async fn hgetall_multi(&self, keys: &[&str]) -> Result<Vec<BTreeMap<String, String>>>;
+
+    async fn hset_multiple(&self, key: &str, values: &[(String, String)]) -> Result<()>;
+
+    /// 原子地执行一批事件计数
+    async fn incr_event_counters(&self, incrs: &[EventIncr]) -> Result<()>;
+
+    /// SET NX PX, key不存在时写入并返回true
+    async fn set_nx_px(&self, key: &str, value: &str, ttl_ms: usize) -> Result<bool>;
+
+    /// 当前值等于value时重置过期时间, 返回是否成功
+    async fn pexpire_if_eq(&self, key: &str, value: &str, ttl_ms: usize) -> Result<bool>;
+
+    /// 当前值等于value时删除, 返回是否成功
+    async fn del_if_eq(&self, key: &str, value: &str) -> Result<bool>;
+
+    /// guard_key的值等于guard_value时才写入hash, 返回是否写入
+    async fn hset_multiple_if_eq(
+        &self,
+        guard_key: &str,
+        guard_value: &str,
+        key: &str,
+        values: &[(String, String)],
+    ) -> Result<bool>;
+
+    async fn publish(&self, channel: &str, message: &str) -> Result<()>;
+
+    /// 订阅频道和模式, 连接断开后自动重连, 接收端drop后停止订阅
+    async fn subscribe(
+        &self,
+        channels: &[&str],
+        patterns: &[&str],
+    ) -> Result<mpsc::UnboundedReceiver<Notification>>;
+}
+
+pub type StorageRef = Arc<dyn Storage>;
+
+pub type RedisPool = bb8::Pool<RedisConnectionManager>;
+
+/// redis连接池配置
+#[derive(Clone, Debug)]
+pub struct RedisPoolCfg {
+    pub url: String,
+    pub max_size: u32,
+    pub min_idle: Option<u32>,
+    pub connection_timeout: Duration,
+    pub idle_timeout: Option<Duration>,
+    /// 取连接时先PING检查连接是否可用
+    pub test_on_check_out: bool,
+}
+
+impl Default for RedisPoolCfg {
+    fn default() -> Self {
+        Self {
+            url: "redis://127.0.0.1".to_string(),
+            max_size: 32,
+            min_idle: None,
+            connection_timeout: Duration::from_secs(3),
+            idle_timeout: Some(Duration::from_secs(10 * 60)),
+            test_on_check_out: true,
+        }
+    }
+}
+
+pub async fn create_redis_pool(cfg: &RedisPoolCfg) -> Result<RedisPool> {
+    let manager = RedisConnectionManager::new(cfg.url.as_str())?;
+    let pool = bb8::Pool::builder()
+        .max_size(cfg.max_size)
+        .min_idle(cfg.min_idle)
+        .connection_timeout(cfg.connection_timeout)
+        .idle_timeout(cfg.idle_timeout)
+        .test_on_check_out(cfg.test_on_check_out)
+        .build(manager)
+        .await?;
+    Ok(pool)
+}
+
+/// 订阅断开后的重连间隔
+const SUBSCRIBE_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 只支持单节点Redis (可带主从): 事件计数脚本、fencing写入和MGET都会同时访问不同slot的key,
+/// 不支持Redis Cluster
+#[derive(Clone)]
+pub struct RedisStorage {
+    pool: RedisPool,
+    /// pub/sub需要独占连接, 不走连接池
+    client: redis::Client,
+    incr_event_script: redis::Script,
+    pexpire_if_eq_script: redis::Script,
+    del_if_eq_script: redis::Script,
+    hset_if_eq_script: redis::Script,
+}
+
+impl RedisStorage {
+    pub fn new(pool: RedisPool, client: redis::Client) -> Self {
+        Self {
+            pool,
+            client,
+            incr_event_script: redis::Script::new(INCR_EVENT_SCRIPT),
+            pexpire_if_eq_script: redis::Script::new(PEXPIRE_IF_EQ_SCRIPT),
+            del_if_eq_script: redis::Script::new(DEL_IF_EQ_SCRIPT),
+            hset_if_eq_script: redis::Script::new(HSET_IF_EQ_SCRIPT),
+        }
+    }
+}
+
+async fn run_subscription(
+    client: &redis::Client,
+    channels: &[String],
+    patterns: &[String],
+    tx: &mpsc::UnboundedSender<Notification>,
+) -> Result<()> {
+    let mut pubsub = client.get_async_connection().await?.into_pubsub();
+    for channel in channels {
+        pubsub.subscribe(channel).await?;
+    }
+    for pattern in patterns {
+        pubsub.psubscribe(pattern).await?;
+    }
+    if tx.send(Notification::Subscribed).is_err() {
+        return Ok(());
+    }
+
+    let mut messages = pubsub.on_message();
+    while let Some(msg) = messages.next().await {
+        let notification = Notification::Message {
+            channel: msg.get_channel_name().to_string(),
+            payload: msg.get_payload().unwrap_or_default(),
+        };
+        if tx.send(notification).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl Storage for RedisStorage {
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        let mut conn = self.pool.get().await?;
+        Ok(conn.get(key).await?)
+    }
+
+    async fn mget(&self, keys: &[&str]) -> Result<Vec<Option<String>>> {
+        if keys.is_empty() {
+            return Ok(vec![]);
+        }
+        let mut conn = self.pool.get().await?;
+        let values: Vec<Option<String>> =
+            redis::cmd("MGET").arg(keys).query_async(&mut *conn).await?;
+        Ok(values)
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl: Option<usize>) -> Result<()> {
+        let mut conn = self.pool.get().await?;
+        let _: () = match ttl {
+            Some(ttl) => conn.set_ex(key, value, ttl).await?,
+            None => conn.set(key, value).await?,
+        };
+        Ok(())
+    }
+
+    async fn incr_by(&self, key: &str, delta: i64) -> Result<i64> {
+        let mut conn = self.pool.get().await?;
+        Ok(conn.incr(key, delta).await?)
+    }
+
+    async fn expire(&self, key: &str, ttl: usize) -> Result<()> {
+        let mut conn = self.pool.get().await?;
+        let _: () = conn.expire(key, ttl).await?;
+        Ok(())
+    }
+
+    async fn hgetall(&self, key: &str) -> Result<BTreeMap<String, String>> {
+        let mut conn = self.pool.get().await?;
+        Ok(conn.hgetall(key).await?)
+    }
+
+    async fn hgetall_multi(&self, keys: &[&str]) -> Result<Vec<BTreeMap<String, String>>> {
+        if keys.is_empty() {
+            return Ok(vec![]);
+        }
+        let mut pipe = redis::pipe();
+        for key in keys {
+            pipe.hgetall(*key);
+        }
+        let mut conn = self.pool.get().await?;
+        Ok(pipe.query_async(&mut *conn).await?)
+    }
+
+    async fn hset_multiple(&self, key: &str, values: &[(String, String)]) -> Result<()> {
+        if values.is_empty() {
+            return Ok(());
+        }
+        let mut conn = self.pool.get().await?;
+        let _: () = conn.hset_multiple(key, values).await?;
+        Ok(())
+    }
+
+    /// 在一个lua脚本内完成所有计数更新, 保证原子性
+    async fn incr_event_counters(&self, incrs: &[EventIncr]) -> Result<()> {
+        if incrs.is_empty() {
+            return Ok(());
+        }
+        let mut invocation = self.incr_event_script.prepare_invoke();
+        for incr in incrs {
+            let idx = incr.field.map_or(0, |f| f + 1);
+            invocation.key(&incr.key).arg(idx).arg(incr.ttl);
+        }
+        let mut conn = self.pool.get().await?;
+        let _: i64 = invocation.invoke_async(&mut *conn).await?;
+        Ok(())
+    }
+
+    async fn set_nx_px(&self, key: &str, value: &str, ttl_ms: usize) -> Result<bool> {
+        let mut conn = self.pool.get().await?;
+        let ret: Option<String> = redis::cmd("SET")
+            .arg(key)
+            .arg(value)
+            .arg("NX")
+            .arg("PX")
+            .arg(ttl_ms)
+            .query_async(&mut *conn)
+            .await?;
+        Ok(ret.is_some())
+    }
+
+    async fn pexpire_if_eq(&self, key: &str, value: &str, ttl_ms: usize) -> Result<bool> {
+        let mut conn = self.pool.get().await?;
+        let ret: i64 = self
+            .pexpire_if_eq_script
+            .key(key)
+            .arg(value)
+            .arg(ttl_ms)
+            .invoke_async(&mut *conn)
+            .await?;
+        Ok(ret == 1)
+    }
+
+    async fn del_if_eq(&self, key: &str, value: &str) -> Result<bool> {
+        let mut conn = self.pool.get().await?;
+        let ret: i64 = self
+            .del_if_eq_script
+            .key(key)
+            .arg(value)
+            .invoke_async(&mut *conn)
+            .await?;
+        Ok(ret == 1)
+    }
+
+    async fn hset_multiple_if_eq(
+        &self,
+        guard_key: &str,
+        guard_value: &str,
+        key: &str,
+        values: &[(String, String)],
+    ) -> Result<bool> {
+        let mut invocation = self.hset_if_eq_script.prepare_invoke();
+        invocation.key(guard_key).key(key).arg(guard_value);
+        for (k, v) in values {
+            invocation.arg(k).arg(v);
+        }
+        let mut conn = self.pool.get().await?;
+        let ret: i64 = invocation.invoke_async(&mut *conn).await?;
+        Ok(ret == 1)
+    }
+
+    async fn publish(&self, channel: &str, message: &str) -> Result<()> {
+        let mut conn = self.pool.get().await?;
+        let _: i64 = conn.publish(channel, message).await?;
+        Ok(())
+    }
+
+    async fn subscribe(
+        &self,
+        channels: &[&str],
+        patterns: &[&str],
+    ) -> Result<mpsc::UnboundedReceiver<Notification>> {
+        let client = self.client.clone();
+        let channels: Vec<String> = channels.iter().map(|c| c.to_string()).collect();
+        let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            while !tx.is_closed() {
+                match run_subscription(&client, &channels, &patterns, &tx).await {
+                    Ok(()) => log::warn!("redis subscription closed"),
+                    Err(e) => log::error!("redis subscription error: {}", e),
+                }
+                tokio::time::sleep(SUBSCRIBE_RETRY_INTERVAL).await;
+            }
+        });
+        Ok(rx)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum MemoryValue {
+    Str(String),
+    Hash(BTreeMap<String, String>),
+}
+
+#[derive(Debug, Clone)]
+struct MemoryEntry {
+    value: MemoryValue,
+    expire_at: Option<Instant>,
+}
+
+struct MemorySubscriber {
+    channels: Vec<String>,
+    patterns: Vec<String>,
+    tx: mpsc::UnboundedSender<Notification>,
+}
+
+impl MemorySubscriber {
+    fn matches(&self, channel: &str) -> bool {
+        self.channels.iter().any(|c| c == channel)
+            || self.patterns.iter().any(|p| glob_match(p, channel))
+    }
+}
+
+/// redis PSUBSCRIBE风格的匹配, 支持 `*` 和 `?`
+fn glob_match(pattern: &str, s: &str) -> bool {
+    let (p, s) = (pattern.as_bytes(), s.as_bytes());
+    let (mut pi, mut si) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while si < s.len() {
+        if pi < p.len() && (p[pi] == b'?' || p[pi] == s[si]) {
+            pi += 1;
+            si += 1;
+        } else if pi < p.len() && p[pi] == b'*' {
+            star = Some((pi, si));
+            pi += 1;
+        } else if let Some((sp, ss)) = star {
+            pi = sp + 1;
+            si = ss + 1;
+            star = Some((sp, ss + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == b'*')
+}
+
+/// 内存存储, 用于测试和本地运行, 语义与redis保持一致
+#[derive(Clone, Default)]
+pub struct MemoryStorage {
+    data: Arc<Mutex<HashMap<String, MemoryEntry>>>,
+    subscribers: Arc<Mutex<Vec<MemorySubscriber>>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn live_entry<'a>(
+        data: &'a mut HashMap<String, MemoryEntry>,
+        key: &str,
+    ) -> Option<&'a mut MemoryEntry> {
+        let expired = match data.get(key) {
+            Some(entry) => entry.expire_at.is_some_and(|t| t <= Instant::now()),
+            None => return None,
+        };
+        if expired {
+            data.remove(key);
+            return None;
+        }
+        data.get_mut(key)
+    }
+
+    fn get_str(data: &mut HashMap<String, MemoryEntry>, key: &str) -> Result<Option<String>> {
+        match Self::live_entry(data, key) {
+            Some(MemoryEntry {
+                value: MemoryValue::Str(v),
+                ..
+            }) => Ok(Some(v.clone())),
+            Some(_) => Err(anyhow!("WRONGTYPE key={} is not a string", key)),
+            None => Ok(None),
+        }
+    }
+
+    fn get_hash(
+        data: &mut HashMap<String, MemoryEntry>,
+        key: &str,
+    ) -> Result<BTreeMap<String, String>> {
+        match Self::live_entry(data, key) {
+            Some(MemoryEntry {
+                value: MemoryValue::Hash(v),
+                ..
+            }) => Ok(v.clone()),
+            Some(_) => Err(anyhow!("WRONGTYPE key={} is not a hash", key)),
+            None => Ok(BTreeMap::new()),
+        }
+    }
+
+    /// 与redis一致: 覆盖写入会清除过期时间
+    fn put_str(data: &mut HashMap<String, MemoryEntry>, key: &str, value: String) {
+        data.insert(
+            key.to_string(),
+            MemoryEntry {
+                value: MemoryValue::Str(value),
+                expire_at: None,
+            },
+        );
+    }
+
+    fn set_expire(data: &mut HashMap<String, MemoryEntry>, key: &str, ttl: usize) {
+        Self::set_expire_ms(data, key, ttl * 1000);
+    }
+
+    fn set_expire_ms(data: &mut HashMap<String, MemoryEntry>, key: &str, ttl_ms: usize) {
+        if let Some(entry) = Self::live_entry(data, key) {
+            entry.expire_at = Some(Instant::now() + Duration::from_millis(ttl_ms as u64));
+        }
+    }
+
+    fn hset(
+        data: &mut HashMap<String, MemoryEntry>,
+        key: &str,
+        values: &[(String, String)],
+    ) -> Result<()> {
+        let entry = match Self::live_entry(data, key) {
+            Some(entry) => entry,
+            None => data.entry(key.to_string()).or_insert(MemoryEntry {
+                value: MemoryValue::Hash(BTreeMap::new()),
+                expire_at: None,
+            }),
+        };
+        match &mut entry.value {
+            MemoryValue::Hash(hash) => {
+                for (k, v) in values {
+                    hash.insert(k.clone(), v.clone());
+                }
+                Ok(())
+            }
+            MemoryValue::Str(_) => Err(anyhow!("WRONGTYPE key={} is not a hash", key)),
+        }
+    }
+
+    fn incr(data: &mut HashMap<String, MemoryEntry>, key: &str, delta: i64) -> Result<i64> {
+        let cur: i64 = match Self::get_str(data, key)? {
+            Some(v) => v
+                .parse()
+                .map_err(|_| anyhow!("value of key={} is not an integer", key))?,
+            None => 0,
+        };
+        let expire_at = Self::live_entry(data, key).and_then(|e| e.expire_at);
+        let new = cur + delta;
+        Self::put_str(data, key, new.to_string());
+        if let Some(entry) = data.get_mut(key) {
+            entry.expire_at = expire_at;
+        }
+        Ok(new)
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        let mut data = self.data.lock().unwrap();
+        Self::get_str(&mut data, key)
+    }
+
+    async fn mget(&self, keys: &[&str]) -> Result<Vec<Option<String>>> {
+        let mut data = self.data.lock().unwrap();
+        // 与MGET一致, 类型不匹配的key返回None
+        Ok(keys
+            .iter()
+            .map(|key| Self::get_str(&mut data, key).unwrap_or_default())
+            .collect())
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl: Option<usize>) -> Result<()> {
+        let mut data = self.data.lock().unwrap();
+        Self::put_str(&mut data, key, value.to_string());
+        if let Some(ttl) = ttl {
+            Self::set_expire(&mut data, key, ttl);
+        }
+        Ok(())
+    }
+
+    async fn incr_by(&self, key: &str, delta: i64) -> Result<i64> {
+        let mut data = self.data.lock().unwrap();
+        Self::incr(&mut data, key, delta)
+    }
+
+    async fn expire(&self, key: &str, ttl: usize) -> Result<()> {
+        let mut data = self.data.lock().unwrap();
+        Self::set_expire(&mut data, key, ttl);
+        Ok(())
+    }
+
+    async fn hgetall(&self, key: &str) -> Result<BTreeMap<String, String>> {
+        let mut data = self.data.lock().unwrap();
+        Self::get_hash(&mut data, key)
+    }
+
+    async fn hgetall_multi(&self, keys: &[&str]) -> Result<Vec<BTreeMap<String, String>>> {
+        let mut data = self.data.lock().unwrap();
+        keys.iter()
+            .map(|key| Self::get_hash(&mut data, key))
+            .collect()
+    }
+
+    async fn hset_multiple(&self, key: &str, values: &[(String, String)]) -> Result<()> {
+        let mut data = self.data.lock().unwrap();
+        Self::hset(&mut data, key, values)
+    }
+
+    async fn incr_event_counters(&self, incrs: &[EventIncr]) -> Result<()> {
+        let mut data = self.data.lock().unwrap();
+        for incr in incrs {
+            match incr.field {
+                None => {
+                    Self::incr(&mut data, &incr.key, 1)?;
+                }
+                Some(field) => {
+                    let mut counts = [0i64; 4];
+                    if let Some(cur) = Self::get_str(&mut data, &incr.key)? {
+                        for (i, v) in cur.split('_').take(4).enumerate() {
+                            counts[i] = v.parse().unwrap_or_default();
+                        }
+                    }
+                    counts[field] += 1;
+                    let value = counts
+                        .iter()
+                        .map(|c| c.to_string())
+                        .collect::<Vec<_>>()
+                        .join("_");
+                    Self::put_str(&mut data, &incr.key, value);
+                }
+            }
+            if incr.ttl > 0 {
+                Self::set_expire(&mut data, &incr.key, incr.ttl);
+            }
+        }
+        Ok(())
+    }
+
+    async fn set_nx_px(&self, key: &str, value: &str, ttl_ms: usize) -> Result<bool> {
+        // 与redis一致, PX 0 是非法的过期时间
+        if ttl_ms == 0 {
+            return Err(anyhow!("invalid expire time in 'set' command, key={}", key));
+        }
+        let mut data = self.data.lock().unwrap();
+        if Self::live_entry(&mut data, key).is_some() {
+            return Ok(false);
+        }
+        Self::put_str(&mut data, key, value.to_string());
+        Self::set_expire_ms(&mut data, key, ttl_ms);
+        Ok(true)
+    }
+
+    async fn pexpire_if_eq(&self, key: &str, value: &str, ttl_ms: usize) -> Result<bool> {
+        let mut data = self.data.lock().unwrap();
+        if Self::get_str(&mut data, key)?.as_deref() != Some(value) {
+            return Ok(false);
+        }
+        Self::set_expire_ms(&mut data, key, ttl_ms);
+        Ok(true)
+    }
+
+    async fn del_if_eq(&self, key: &str, value: &str) -> Result<bool> {
+        let mut data = self.data.lock().unwrap();
+        if Self::get_str(&mut data, key)?.as_deref() != Some(value) {
+            return Ok(false);
+        }
+        data.remove(key);
+        Ok(true)
+    }
+
+    async fn hset_multiple_if_eq(
+        &self,
+        guard_key: &str,
+        guard_value: &str,
+        key: &str,
+        values: &[(String, String)],
+    ) -> Result<bool> {
+        let mut data = self.data.lock().unwrap();
+        if Self::get_str(&mut data, guard_key)?.as_deref() != Some(guard_value) {
+            return Ok(false);
+        }
+        Self::hset(&mut data, key, values)?;
+        Ok(true)
+    }
+
+    async fn publish(&self, channel: &str, message: &str) -> Result<()> {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|sub| !sub.tx.is_closed());
+        for sub in subscribers.iter().filter(|sub| sub.matches(channel)) {
+            let _ = sub.tx.send(Notification::Message {
+                channel: channel.to_string(),
+                payload: message.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    async fn subscribe(
+        &self,
+        channels: &[&str],
+        patterns: &[&str],
+    ) -> Result<mpsc::UnboundedReceiver<Notification>> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let _ = tx.send(Notification::Subscribed);
+        self.subscribers.lock().unwrap().push(MemorySubscriber {
+            channels: channels.iter().map(|c| c.to_string()).collect(),
+            patterns: patterns.iter().map(|p| p.to_string()).collect(),
+            tx,
+        });
+        Ok(rx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_memory_storage_strings() {
+        let storage = MemoryStorage::new();
+        storage.set("a", "1", None).await.unwrap();
+        assert_eq!(storage.get("a").await.unwrap(), Some("1".to_string()));
+        assert_eq!(storage.incr_by("a", 2).await.unwrap(), 3);
+        assert_eq!(
+            storage.mget(&["a", "b"]).await.unwrap(),
+            vec![Some("3".to_string()), None]
+        );
+
+        storage.set("b", "x", Some(60)).await.unwrap();
+        storage.expire("b", 0).await.unwrap();
+        assert_eq!(storage.get("b").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_memory_storage_hash() {
+        let storage = MemoryStorage::new();
+        storage
+            .hset_multiple("h", &[("k1".to_string(), "v1".to_string())])
+            .await
+            .unwrap();
+        storage
+            .hset_multiple("h", &[("k2".to_string(), "v2".to_string())])
+            .await
+            .unwrap();
+        assert_eq!(storage.hgetall("h").await.unwrap().len(), 2);
+        assert!(storage.get("h").await.is_err());
+        assert!(storage.hgetall("missing").await.unwrap().is_empty());
+        let hashes = storage.hgetall_multi(&["h", "missing"]).await.unwrap();
+        assert_eq!(hashes.len(), 2);
+        assert_eq!(hashes[0].len(), 2);
+        assert!(hashes[1].is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_memory_storage_lease() {
+        let storage = MemoryStorage::new();
+        assert!(storage.set_nx_px("l", "a", 60_000).await.unwrap());
+        assert!(!storage.set_nx_px("l", "b", 60_000).await.unwrap());
+        assert!(storage.pexpire_if_eq("l", "a", 60_000).await.unwrap());
+        assert!(!storage.pexpire_if_eq("l", "b", 60_000).await.unwrap());
+        assert!(!storage.del_if_eq("l", "b").await.unwrap());
+        assert!(!storage
+            .hset_multiple_if_eq("l", "b", "h", &[("k".to_string(), "v".to_string())])
+            .await
+            .unwrap());
+        assert!(storage
+            .hset_multiple_if_eq("l", "a", "h", &[("k".to_string(), "v".to_string())])
+            .await
+            .unwrap());
+        assert_eq!(storage.hgetall("h").await.unwrap().len(), 1);
+        assert!(storage.del_if_eq("l", "a").await.unwrap());
+        assert!(storage.set_nx_px("l", "b", 0).await.is_err());
+        assert!(storage.set_nx_px("l", "a", 60_000).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_memory_storage_event_counters() {
+        let storage = MemoryStorage::new();
+        let incrs = vec![
+            EventIncr {
+                key: "e".to_string(),
+                field: Some(0),
+                ttl: 60,
+            },
+            EventIncr {
+                key: "e".to_string(),
+                field: Some(3),
+                ttl: 60,
+            },
+            EventIncr {
+                key: "c".to_string(),
+                field: None,
+                ttl: 0,
+            },
+        ];
+        storage.incr_event_counters(&incrs).await.unwrap();
+        storage.incr_event_counters(&incrs).await.unwrap();
+        assert_eq!(storage.get("e").await.unwrap(), Some("2_0_0_2".to_string()));
+        assert_eq!(storage.get("c").await.unwrap(), Some("2".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_memory_storage_pubsub() {
+        assert!(glob_match(
+            "__keyspace@*__:cfg:*",
+            "__keyspace@0__:cfg:exp:base"
+        ));
+        assert!(!glob_match(
+            "__keyspace@*__:cfg:*",
+            "__keyspace@0__:daily:1"
+        ));
+
+        let storage = MemoryStorage::new();
+        let mut rx = storage
+            .subscribe(&["cfg:changed"], &["__keyspace@*__:cfg:*"])
+            .await
+            .unwrap();
+        assert_eq!(rx.recv().await, Some(Notification::Subscribed));
+
+        storage.publish("other", "x").await.unwrap();
+        storage
+            .publish("cfg:changed", "cfg:exp:base")
+            .await
+            .unwrap();
+        storage
+            .publish("__keyspace@0__:cfg:exp:ab", "hset")
+            .await
+            .unwrap();
+        assert_eq!(
+            rx.recv().await,
+            Some(Notification::Message {
+                channel: "cfg:changed".to_string(),
+                payload: "cfg:exp:base".to_string(),
+            })
+        );
+        assert_eq!(
+            rx.recv().await,
+            Some(Notification::Message {
+                channel: "__keyspace@0__:cfg:exp:ab".to_string(),
+                payload: "hset".to_string(),
+            })
+        );
+    }
+}
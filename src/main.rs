@@ -1,4 +1,4 @@
-use std::{future::ready, time::Instant};
+use std::{future::ready, sync::Arc, time::Instant};
 
 use axum::{
     extract::MatchedPath,
@@ -10,25 +10,58 @@ use axum::{
 };
 use log;
 
-use crate::{dao::*, service::*};
+use crate::{dao::*, service::*, settings::*};
 use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
 
 mod api;
 mod dao;
 mod model;
 mod service;
+mod settings;
 
 #[tokio::main]
 async fn main() {
+    let settings = match load_settings() {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            std::process::exit(2);
+        }
+    };
+
     env_logger::init_from_env(
-        env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "debug"),
+        env_logger::Env::default()
+            .filter_or(env_logger::DEFAULT_FILTER_ENV, &settings.server.log_level),
     );
 
     log::info!("----start smarty-adserver---------");
-    let redis = redis::Client::open("redis://127.0.0.1").unwrap();
+    log::info!("settings: {:?}", settings);
+    let storage: StorageRef = match settings.storage.backend {
+        StorageBackend::Redis => {
+            let redis_pool = create_redis_pool(&settings.redis.pool_cfg()).await.unwrap();
+            let client = redis::Client::open(settings.redis.url.as_str()).unwrap();
+            Arc::new(RedisStorage::new(redis_pool, client))
+        }
+        StorageBackend::Memory => Arc::new(MemoryStorage::new()),
+    };
 
-    let ads_db = AdsDB::new(redis.clone());
+    let ads_db = AdsDB::new_with_cfg(storage.clone(), &settings.ads_db_cfg()).await;
     let prediction_service = ProdictionService::new(ads_db.clone());
+    let event_service = EventService::new(ads_db.clone());
+    let analysis_service = AnalysisService::new(ads_db.clone());
+    let leader = LeaderElector::new(
+        storage.clone(),
+        settings
+            .leader
+            .node_id
+            .clone()
+            .unwrap_or_else(LeaderElector::default_node_id),
+        settings.leader_lease(),
+    );
+    leader.start();
+    let exp_driver =
+        ExpDriver::new(ads_db.clone(), leader.clone()).with_cron(&settings.exp_driver.cron);
+    exp_driver.start();
 
     let recorder_handle = setup_metrics_recorder();
 
@@ -36,17 +69,74 @@ async fn main() {
     let app = Router::new()
         .route("/", get(ping).head(ping))
         .route("/api/predict", post(api::predict))
+        .route("/api/event", post(api::track_event))
+        .route("/api/event/batch", post(api::track_events))
+        .route("/api/status", get(api::status))
+        .route("/api/admin/config/changes", get(api::config_changes))
+        .route(
+            "/api/admin/experiments/:version/report",
+            get(api::experiment_report),
+        )
         .route("/api/test", get(api::test))
         .route("/metrics", get(move || ready(recorder_handle.render())))
         .route_layer(middleware::from_fn(track_metrics))
         .layer(Extension(ads_db))
-        .layer(Extension(prediction_service));
+        .layer(Extension(prediction_service))
+        .layer(Extension(event_service))
+        .layer(Extension(analysis_service))
+        .layer(Extension(leader.clone()));
 
-    log::info!("start server on port 3000");
-    axum::Server::bind(&"0.0.0.0:3000".parse().unwrap())
+    log::info!("start server on {}", settings.server.addr);
+    axum::Server::bind(&settings.server.addr.parse().unwrap())
         .serve(app.into_make_service())
+        .with_graceful_shutdown(shutdown_signal())
         .await
         .unwrap();
+
+    // 主动释放租约, 其他节点无需等待租约过期即可接管
+    leader.resign().await;
+    log::info!("----stop smarty-adserver---------");
+}
+
+/// 等待 ctrl-c 或 SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            log::error!("listen ctrl-c error: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sig) => {
+                sig.recv().await;
+            }
+            Err(e) => {
+                log::error!("listen SIGTERM error: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    log::info!("shutdown signal received");
+}
+
+/// 合并配置文件、环境变量和命令行参数, --help时打印用法后退出
+fn load_settings() -> anyhow::Result<Settings> {
+    let cli = Cli::parse(std::env::args().skip(1))?;
+    if cli.help {
+        print!("{}", Cli::usage());
+        std::process::exit(0);
+    }
+    Settings::load(&cli, |name| std::env::var(name).ok())
 }
 
 async fn ping() -> &'static str {
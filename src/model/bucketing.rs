@@ -0,0 +1,323 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, bail, Result};
+use serde::Serialize;
+
+/// 没有配置层时的默认层名
+pub const DEFAULT_LAYER: &str = "default";
+pub const DEFAULT_BUCKETS: u32 = 1000;
+/// 分桶实验中不属于任何分组的用户
+pub const GROUP_MAIN: &str = "main";
+
+/// 实验分组
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Group {
+    Control,
+    Experiment,
+    /// 留出组, 不参与实验也不计入对照组
+    Holdout,
+}
+
+impl Group {
+    /// 用作事件计数key中的用户分组
+    pub fn label(&self) -> &'static str {
+        match self {
+            Group::Control => "cg",
+            Group::Experiment => "eg",
+            Group::Holdout => "holdout",
+        }
+    }
+}
+
+/// 实验层, 同一层内的实验按桶划分流量互不重叠, 不同层使用不同的salt相互正交
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Layer {
+    pub salt: String,
+    pub buckets: u32,
+}
+
+/// 实验在所在层的流量分配, 均为百分比
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ExpSplit {
+    pub layer: String,
+    /// 从层的第几个百分点开始
+    pub offset: f64,
+    pub control: f64,
+    pub experiment: f64,
+    pub holdout: f64,
+}
+
+/// 分桶结果
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Assignment {
+    pub bucket: u32,
+    /// 不在实验流量内时为None
+    pub group: Option<Group>,
+}
+
+/// 按实验版本配置的分桶
+///
+/// hash字段: `layer:<name>:salt` (默认为层名), `layer:<name>:buckets` (默认1000);
+/// `exp:<version>:layer` (默认 `default`), `exp:<version>:offset`,
+/// `exp:<version>:control`, `exp:<version>:experiment`, `exp:<version>:holdout` 为百分比
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BucketingCfg {
+    pub layers: BTreeMap<String, Layer>,
+    pub experiments: BTreeMap<String, ExpSplit>,
+}
+
+impl BucketingCfg {
+    pub fn parse(hash: &BTreeMap<String, String>) -> Result<Self> {
+        let mut cfg = Self::default();
+        for (k, v) in hash {
+            let (scope, rest) = k.split_once(':').ok_or_else(|| {
+                anyhow!(
+                    "{}: expect layer:<name>:<field> or exp:<version>:<field>",
+                    k
+                )
+            })?;
+            let (id, name) = rest
+                .rsplit_once(':')
+                .ok_or_else(|| anyhow!("{}: expect {}:<id>:<field>", k, scope))?;
+            match scope {
+                "layer" => {
+                    let layer = cfg.layers.entry(id.to_string()).or_insert_with(|| Layer {
+                        salt: id.to_string(),
+                        buckets: DEFAULT_BUCKETS,
+                    });
+                    match name {
+                        "salt" => layer.salt = v.trim().to_string(),
+                        "buckets" => {
+                            layer.buckets = v
+                                .trim()
+                                .parse()
+                                .map_err(|_| anyhow!("{}={:?} is not a bucket count", k, v))?
+                        }
+                        _ => bail!("{}: unknown field {}", k, name),
+                    }
+                }
+                "exp" => {
+                    let split = cfg
+                        .experiments
+                        .entry(id.to_string())
+                        .or_insert_with(|| ExpSplit {
+                            layer: DEFAULT_LAYER.to_string(),
+                            ..Default::default()
+                        });
+                    match name {
+                        "layer" => split.layer = v.trim().to_string(),
+                        "offset" => split.offset = percent(k, v)?,
+                        "control" => split.control = percent(k, v)?,
+                        "experiment" => split.experiment = percent(k, v)?,
+                        "holdout" => split.holdout = percent(k, v)?,
+                        _ => bail!("{}: unknown field {}", k, name),
+                    }
+                }
+                _ => bail!("{}: unknown scope {}", k, scope),
+            }
+        }
+        cfg.validate()?;
+        Ok(cfg)
+    }
+
+    fn validate(&self) -> Result<()> {
+        for (name, layer) in &self.layers {
+            if layer.salt.is_empty() {
+                bail!("layer {}: salt must not be empty", name);
+            }
+            if layer.buckets == 0 {
+                bail!("layer {}: buckets must be positive", name);
+            }
+        }
+
+        let mut used: BTreeMap<&str, Vec<(u32, u32, &str)>> = BTreeMap::new();
+        for (version, split) in &self.experiments {
+            let total = split.offset + split.control + split.experiment + split.holdout;
+            if total > 100.0 {
+                bail!(
+                    "exp {}: offset and splits add up to {}% > 100%",
+                    version,
+                    total
+                );
+            }
+            if split.control <= 0.0 || split.experiment <= 0.0 {
+                bail!("exp {}: control and experiment must be positive", version);
+            }
+            let (start, end) = self.range(split);
+            used.entry(&split.layer)
+                .or_default()
+                .push((start, end, version));
+        }
+        for (layer, ranges) in used.iter_mut() {
+            ranges.sort();
+            for pair in ranges.windows(2) {
+                if pair[1].0 < pair[0].1 {
+                    bail!(
+                        "layer {}: exp {} overlaps exp {}",
+                        layer,
+                        pair[0].2,
+                        pair[1].2
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn layer(&self, name: &str) -> Layer {
+        self.layers.get(name).cloned().unwrap_or_else(|| Layer {
+            salt: name.to_string(),
+            buckets: DEFAULT_BUCKETS,
+        })
+    }
+
+    /// 实验在层内占用的桶 [start, end)
+    fn range(&self, split: &ExpSplit) -> (u32, u32) {
+        let buckets = self.layer(&split.layer).buckets;
+        let total = split.offset + split.control + split.experiment + split.holdout;
+        (edge(split.offset, buckets), edge(total, buckets))
+    }
+
+    /// 版本没有配置分桶时返回None
+    pub fn assign(&self, version: &str, usr: &str) -> Option<Assignment> {
+        let split = self.experiments.get(version)?;
+        let layer = self.layer(&split.layer);
+        let bucket = bucket(&layer.salt, usr, layer.buckets);
+
+        let mut start = split.offset;
+        let mut group = None;
+        for (g, pct) in [
+            (Group::Control, split.control),
+            (Group::Experiment, split.experiment),
+            (Group::Holdout, split.holdout),
+        ] {
+            let end = start + pct;
+            if bucket >= edge(start, layer.buckets) && bucket < edge(end, layer.buckets) {
+                group = Some(g);
+                break;
+            }
+            start = end;
+        }
+        Some(Assignment { bucket, group })
+    }
+
+    /// 分桶实验中的用户分组, 版本没有配置分桶时返回None
+    pub fn usergroup(&self, version: &str, usr: &str) -> Option<&'static str> {
+        self.assign(version, usr)
+            .map(|a| a.group.map_or(GROUP_MAIN, |g| g.label()))
+    }
+}
+
+/// md5(salt:usr) 的前8字节对桶数取模
+pub fn bucket(salt: &str, usr: &str, buckets: u32) -> u32 {
+    let digest = md5::compute(format!("{}:{}", salt, usr));
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&digest[..8]);
+    (u64::from_be_bytes(bytes) % buckets.max(1) as u64) as u32
+}
+
+/// 百分比对应的桶边界
+fn edge(pct: f64, buckets: u32) -> u32 {
+    (pct * buckets as f64 / 100.0).round() as u32
+}
+
+fn percent(key: &str, s: &str) -> Result<f64> {
+    let v: f64 = s
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("{}={:?} is not a number", key, s))?;
+    if !(0.0..=100.0).contains(&v) {
+        bail!("{}={} must be a percentage in [0, 100]", key, v);
+    }
+    Ok(v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::hash;
+
+    #[test]
+    fn test_traffic_split() {
+        let cfg = BucketingCfg::parse(&hash(&[
+            ("exp:1:control", "10"),
+            ("exp:1:experiment", "10"),
+            ("exp:1:holdout", "5"),
+        ]))
+        .unwrap();
+        assert!(cfg.assign("2", "u1").is_none());
+
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+        for i in 0..20000 {
+            let usr = format!("u{}", i);
+            let a = cfg.assign("1", &usr).unwrap();
+            assert!(a.bucket < DEFAULT_BUCKETS);
+            // 同一用户总是分到同一组
+            assert_eq!(cfg.assign("1", &usr), Some(a));
+            *counts.entry(cfg.usergroup("1", &usr).unwrap()).or_default() += 1;
+        }
+        let share = |g: &str| counts[g] as f64 / 20000.0;
+        assert!((share("cg") - 0.10).abs() < 0.02);
+        assert!((share("eg") - 0.10).abs() < 0.02);
+        assert!((share("holdout") - 0.05).abs() < 0.02);
+        assert!((share(GROUP_MAIN) - 0.75).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_layers() {
+        let cfg = BucketingCfg::parse(&hash(&[
+            ("layer:ctr:salt", "s1"),
+            ("layer:ctr:buckets", "100"),
+            ("exp:1:layer", "ctr"),
+            ("exp:1:control", "20"),
+            ("exp:1:experiment", "20"),
+            ("exp:2:layer", "ctr"),
+            ("exp:2:offset", "40"),
+            ("exp:2:control", "30"),
+            ("exp:2:experiment", "30"),
+            ("exp:3:control", "50"),
+            ("exp:3:experiment", "50"),
+        ]))
+        .unwrap();
+
+        let mut both = 0;
+        for i in 0..2000 {
+            let usr = format!("u{}", i);
+            let a1 = cfg.assign("1", &usr).unwrap();
+            let a2 = cfg.assign("2", &usr).unwrap();
+            // 同一层内的实验互不重叠
+            assert!(a1.group.is_none() || a2.group.is_none());
+            assert!(a1.group.is_some() || a2.group.is_some());
+            if a1.group == Some(Group::Experiment)
+                && cfg.assign("3", &usr).unwrap().group == Some(Group::Experiment)
+            {
+                both += 1;
+            }
+        }
+        // 不同层正交: 约 20% * 50%
+        assert!((both as f64 / 2000.0 - 0.1).abs() < 0.03);
+    }
+
+    #[test]
+    fn test_invalid_cfg() {
+        assert!(BucketingCfg::parse(&hash(&[
+            ("exp:1:control", "60"),
+            ("exp:1:experiment", "50")
+        ]))
+        .is_err());
+        assert!(BucketingCfg::parse(&hash(&[("exp:1:control", "10")])).is_err());
+        assert!(BucketingCfg::parse(&hash(&[("exp:1:control", "-1")])).is_err());
+        assert!(BucketingCfg::parse(&hash(&[("layer:a:buckets", "0")])).is_err());
+        assert!(BucketingCfg::parse(&hash(&[("salt", "x")])).is_err());
+        assert!(BucketingCfg::parse(&hash(&[("exp:1:weight", "1")])).is_err());
+        assert!(BucketingCfg::parse(&hash(&[
+            ("exp:1:control", "30"),
+            ("exp:1:experiment", "30"),
+            ("exp:2:offset", "50"),
+            ("exp:2:control", "10"),
+            ("exp:2:experiment", "10"),
+        ]))
+        .is_err());
+    }
+}
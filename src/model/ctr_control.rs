@@ -0,0 +1,319 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, bail, Result};
+use serde::Serialize;
+
+use super::CtrBranch;
+
+/// 一档偏差区间: 窗口CTR低于目标超过 deviation 时乘 boost, 高于目标超过 deviation 时乘 damp
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CtrBand {
+    /// 相对目标CTR的偏差比例, 如0.3表示±30%
+    pub deviation: f64,
+    pub boost: f64,
+    pub damp: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CtrControlMode {
+    /// 分档调整, 命中偏差最大的一档
+    Bands,
+    /// 比例控制: factor = 1 + kp * (target - window) / target, 限制在 [min_factor, max_factor]
+    Proportional,
+}
+
+/// 目标CTR调整方式
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CtrControl {
+    pub mode: CtrControlMode,
+    /// 按deviation升序
+    pub bands: Vec<CtrBand>,
+    pub kp: f64,
+    pub min_factor: f64,
+    pub max_factor: f64,
+}
+
+impl Default for CtrControl {
+    /// ±30%, 低于目标乘2, 高于目标乘0.5
+    fn default() -> Self {
+        Self {
+            mode: CtrControlMode::Bands,
+            bands: vec![CtrBand {
+                deviation: 0.3,
+                boost: 2.0,
+                damp: 0.5,
+            }],
+            kp: 1.0,
+            min_factor: 0.5,
+            max_factor: 2.0,
+        }
+    }
+}
+
+/// 调整结果
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CtrAdjust {
+    pub branch: CtrBranch,
+    /// total_rate的乘数
+    pub factor: f64,
+    /// 命中的档位下标, 比例控制时为None
+    pub tier: Option<usize>,
+}
+
+impl CtrAdjust {
+    fn none() -> Self {
+        Self {
+            branch: CtrBranch::None,
+            factor: 1.0,
+            tier: None,
+        }
+    }
+}
+
+impl CtrControl {
+    /// 从字段解析, 缺少的字段使用默认值
+    fn parse(fields: &BTreeMap<&str, &str>) -> Result<Self> {
+        let mut control = Self::default();
+        for (&name, &v) in fields {
+            match name {
+                "mode" => {
+                    control.mode = match v.trim() {
+                        "bands" => CtrControlMode::Bands,
+                        "proportional" => CtrControlMode::Proportional,
+                        other => bail!("mode={:?}, expect bands or proportional", other),
+                    }
+                }
+                "bands" => control.bands = parse_bands(v)?,
+                "kp" => control.kp = parse_f64(name, v)?,
+                "min_factor" => control.min_factor = parse_f64(name, v)?,
+                "max_factor" => control.max_factor = parse_f64(name, v)?,
+                _ => bail!("unknown field {}", name),
+            }
+        }
+
+        if control.kp < 0.0 {
+            bail!("kp={} must be non-negative", control.kp);
+        }
+        if !(control.min_factor > 0.0 && control.min_factor <= 1.0 && control.max_factor >= 1.0) {
+            bail!(
+                "expect 0 < min_factor({}) <= 1 <= max_factor({})",
+                control.min_factor,
+                control.max_factor
+            );
+        }
+        Ok(control)
+    }
+
+    pub fn adjust(&self, window_ctr: f64, target_ctr: f64) -> CtrAdjust {
+        match self.mode {
+            CtrControlMode::Bands => self.adjust_bands(window_ctr, target_ctr),
+            CtrControlMode::Proportional => self.adjust_proportional(window_ctr, target_ctr),
+        }
+    }
+
+    fn adjust_bands(&self, window_ctr: f64, target_ctr: f64) -> CtrAdjust {
+        // 区间判断 [-N,-dev, dev,+N], 从偏差最大的一档开始匹配
+        for (tier, band) in self.bands.iter().enumerate().rev() {
+            if window_ctr + target_ctr * band.deviation < target_ctr {
+                return CtrAdjust {
+                    branch: CtrBranch::A,
+                    factor: band.boost,
+                    tier: Some(tier),
+                };
+            }
+            if target_ctr + target_ctr * band.deviation < window_ctr {
+                return CtrAdjust {
+                    branch: CtrBranch::C,
+                    factor: band.damp,
+                    tier: Some(tier),
+                };
+            }
+        }
+        CtrAdjust::none()
+    }
+
+    fn adjust_proportional(&self, window_ctr: f64, target_ctr: f64) -> CtrAdjust {
+        let factor = if target_ctr > 0.0 {
+            1.0 + self.kp * (target_ctr - window_ctr) / target_ctr
+        } else if window_ctr > 0.0 {
+            self.min_factor
+        } else {
+            1.0
+        };
+        let factor = factor.clamp(self.min_factor, self.max_factor);
+        let branch = if factor > 1.0 {
+            CtrBranch::A
+        } else if factor < 1.0 {
+            CtrBranch::C
+        } else {
+            CtrBranch::None
+        };
+        CtrAdjust {
+            branch,
+            factor,
+            tier: None,
+        }
+    }
+}
+
+/// 目标CTR调整配置, 按 广告 > 实验版本 > 默认 的优先级生效
+///
+/// hash字段: `mode`, `bands`, `kp`, `min_factor`, `max_factor` 为默认配置;
+/// `version:<version>:<field>` 和 `ad:<ad_id>:<field>` 为对应版本和广告的配置,
+/// 每个作用域单独生效, 没有配置的字段使用内置默认值.
+/// `bands` 格式为 `deviation:boost:damp,...`, 如 `0.1:1.2:0.9,0.3:2:0.5`
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct CtrControlCfg {
+    pub default: CtrControl,
+    pub versions: BTreeMap<String, CtrControl>,
+    pub ads: BTreeMap<i64, CtrControl>,
+}
+
+impl CtrControlCfg {
+    pub fn parse(hash: &BTreeMap<String, String>) -> Result<Self> {
+        let mut default = BTreeMap::new();
+        let mut versions: BTreeMap<&str, BTreeMap<&str, &str>> = BTreeMap::new();
+        let mut ads: BTreeMap<i64, BTreeMap<&str, &str>> = BTreeMap::new();
+        for (k, v) in hash {
+            match k.split_once(':') {
+                None => {
+                    default.insert(k.as_str(), v.as_str());
+                }
+                Some((scope, rest)) => {
+                    let (id, name) = rest
+                        .rsplit_once(':')
+                        .ok_or_else(|| anyhow!("{}: expect {}:<id>:<field>", k, scope))?;
+                    match scope {
+                        "version" => {
+                            versions.entry(id).or_default().insert(name, v);
+                        }
+                        "ad" => {
+                            let ad_id = id
+                                .parse()
+                                .map_err(|_| anyhow!("{}: {:?} is not an ad id", k, id))?;
+                            ads.entry(ad_id).or_default().insert(name, v);
+                        }
+                        _ => bail!("{}: unknown scope {}", k, scope),
+                    }
+                }
+            }
+        }
+
+        let scoped = |scope: &str, fields| {
+            CtrControl::parse(fields).map_err(|e| anyhow!("{}: {}", scope, e))
+        };
+        Ok(Self {
+            default: scoped("default", &default)?,
+            versions: versions
+                .iter()
+                .map(|(v, fields)| Ok((v.to_string(), scoped(v, fields)?)))
+                .collect::<Result<_>>()?,
+            ads: ads
+                .iter()
+                .map(|(ad_id, fields)| Ok((*ad_id, scoped(&ad_id.to_string(), fields)?)))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    pub fn resolve(&self, version: &str, ad_id: i64) -> &CtrControl {
+        self.ads
+            .get(&ad_id)
+            .or_else(|| self.versions.get(version))
+            .unwrap_or(&self.default)
+    }
+}
+
+fn parse_bands(s: &str) -> Result<Vec<CtrBand>> {
+    let mut bands = Vec::new();
+    for tier in s.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+        let parts: Vec<&str> = tier.split(':').collect();
+        if parts.len() != 3 {
+            bail!("band {:?}, expect deviation:boost:damp", tier);
+        }
+        let band = CtrBand {
+            deviation: parse_f64("deviation", parts[0])?,
+            boost: parse_f64("boost", parts[1])?,
+            damp: parse_f64("damp", parts[2])?,
+        };
+        if band.deviation < 0.0 || band.boost <= 0.0 || band.damp <= 0.0 {
+            bail!(
+                "band {:?}, expect deviation >= 0, boost > 0, damp > 0",
+                tier
+            );
+        }
+        bands.push(band);
+    }
+    bands.sort_by(|a, b| a.deviation.total_cmp(&b.deviation));
+    if bands.windows(2).any(|w| w[0].deviation == w[1].deviation) {
+        bail!("bands {:?} have duplicate deviation", s);
+    }
+    Ok(bands)
+}
+
+fn parse_f64(name: &str, s: &str) -> Result<f64> {
+    let v: f64 = s
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("{}={:?} is not a number", name, s))?;
+    if !v.is_finite() {
+        bail!("{}={} must be finite", name, v);
+    }
+    Ok(v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::hash;
+
+    #[test]
+    fn test_default_matches_legacy_band() {
+        let control = CtrControl::default();
+        assert_eq!(control.adjust(0.5, 1.0).factor, 2.0);
+        assert_eq!(control.adjust(0.75, 1.0).branch, CtrBranch::None);
+        assert_eq!(control.adjust(1.2, 1.0).factor, 1.0);
+        assert_eq!(control.adjust(1.5, 1.0).factor, 0.5);
+        assert_eq!(control.adjust(1.5, 1.0).branch, CtrBranch::C);
+    }
+
+    #[test]
+    fn test_tiers_and_scopes() {
+        let cfg = CtrControlCfg::parse(&hash(&[
+            ("bands", "0.6:3:0.3, 0.1:1.2:0.9, 0.3:2:0.5"),
+            ("version:2:mode", "proportional"),
+            ("version:2:kp", "0.5"),
+            ("ad:7:bands", ""),
+        ]))
+        .unwrap();
+
+        let control = cfg.resolve("1", 1);
+        assert_eq!(control.adjust(0.85, 1.0).tier, Some(0));
+        assert_eq!(control.adjust(0.85, 1.0).factor, 1.2);
+        assert_eq!(control.adjust(0.5, 1.0).factor, 2.0);
+        assert_eq!(control.adjust(0.2, 1.0).factor, 3.0);
+        assert_eq!(control.adjust(1.7, 1.0).factor, 0.3);
+
+        // 比例控制: 1 + 0.5 * (1 - 0.6) / 1
+        let adjust = cfg.resolve("2", 1).adjust(0.6, 1.0);
+        assert!((adjust.factor - 1.2).abs() < 1e-9);
+        assert_eq!(adjust.branch, CtrBranch::A);
+        assert_eq!(cfg.resolve("2", 1).adjust(0.0, 1.0).factor, 1.5);
+        assert_eq!(cfg.resolve("2", 1).adjust(9.0, 1.0).factor, 0.5);
+
+        // 广告配置优先于版本配置, 空bands表示不调整
+        assert_eq!(cfg.resolve("2", 7).adjust(0.0, 1.0), CtrAdjust::none());
+    }
+
+    #[test]
+    fn test_invalid_cfg() {
+        assert!(CtrControlCfg::parse(&hash(&[("bands", "0.3:2")])).is_err());
+        assert!(CtrControlCfg::parse(&hash(&[("bands", "0.3:2:0.5,0.3:3:0.1")])).is_err());
+        assert!(CtrControlCfg::parse(&hash(&[("bands", "0.3:0:0.5")])).is_err());
+        assert!(CtrControlCfg::parse(&hash(&[("mode", "pid")])).is_err());
+        assert!(CtrControlCfg::parse(&hash(&[("min_factor", "1.5")])).is_err());
+        assert!(CtrControlCfg::parse(&hash(&[("ad:x:kp", "1")])).is_err());
+        assert!(CtrControlCfg::parse(&hash(&[("campaign:1:kp", "1")])).is_err());
+        assert!(CtrControlCfg::parse(&hash(&[("gain", "1")])).is_err());
+    }
+}
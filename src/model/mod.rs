@@ -1,13 +1,39 @@
 use chrono::{DateTime, Local};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+pub mod bucketing;
+pub mod ctr_control;
+pub mod range;
+
+pub use bucketing::*;
+pub use ctr_control::*;
+pub use range::*;
+
+/// 测试用: 由键值对构造配置hash
+#[cfg(test)]
+pub(crate) fn hash(pairs: &[(&str, &str)]) -> std::collections::BTreeMap<String, String> {
+    pairs
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Request {
     pub usr: String,
     pub ad_id: Vec<i64>,
     pub service_type: i64,
     pub model: Option<String>,
     pub is_debug: Option<bool>,
+    /// 请求id, 确定性抽样时参与计算
+    #[serde(default)]
+    pub request_id: Option<String>,
+    /// 返回每个广告的得分、抽样随机数和排名
+    #[serde(default)]
+    pub with_scores: Option<bool>,
+    /// 按得分取前K个广告投放, 代替逐个广告随机抽样
+    #[serde(default)]
+    pub top_k: Option<usize>,
 }
 
 impl Request {
@@ -18,6 +44,8 @@ impl Request {
             return (false, "广告ID列表不能为空".to_string());
         } else if self.service_type == 0 {
             return (false, "ServiceType只能为1或2".to_string());
+        } else if self.top_k == Some(0) {
+            return (false, "top_k必须大于0".to_string());
         }
         return (true, "".to_string());
     }
@@ -34,14 +62,148 @@ pub struct Response {
 pub struct AdItem {
     pub ad_id: i64,
     pub value: u8,
+    /// with_scores时返回: 策略得分, 规则策略为total_rate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score: Option<f64>,
+    /// with_scores时返回: 抽样使用的随机数, 未抽样时为空
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub probability: Option<f64>,
+    /// with_scores时返回: 按得分从高到低的排名, 从1开始
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rank: Option<usize>,
+    /// bandit策略选中的动作, 上报事件时带回
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action_id: Option<String>,
+    /// is_debug时返回决策过程
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub explain: Option<AdExplain>,
 }
 
 impl AdItem {
     pub fn new(ad_id: i64, value: u8) -> Self {
-        Self { ad_id, value }
+        Self {
+            ad_id,
+            value,
+            score: None,
+            probability: None,
+            rank: None,
+            action_id: None,
+            explain: None,
+        }
+    }
+}
+
+/// 目标CTR调整分支
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum CtrBranch {
+    /// 窗口CTR低于目标, 加大投放
+    A,
+    /// 窗口CTR高于目标, 减少投放
+    C,
+    #[serde(rename = "none")]
+    #[default]
+    None,
+}
+
+/// 单个广告的决策过程, 用于排查为什么某个广告没有投放给某个用户
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AdExplain {
+    pub strategy: String,
+    pub usergroup: String,
+    pub is_exp_group: bool,
+    /// 用户当日诱导点击次数
+    pub tempt_click: f64,
+    pub fill_rate: f64,
+    pub show_rate: f64,
+    pub click_rate: f64,
+    /// 各信号命中的区间, 未命中时为None
+    pub bucket_a: Option<RangeValue>,
+    pub bucket_b: Option<RangeValue>,
+    pub bucket_c: Option<RangeValue>,
+    pub bucket_d: Option<RangeValue>,
+    pub rate_a: f64,
+    pub rate_b: f64,
+    pub rate_c: f64,
+    pub rate_d: f64,
+    pub window_ctr: f64,
+    pub target_ctr: f64,
+    pub branch: CtrBranch,
+    /// 目标CTR调整的乘数
+    pub ctr_factor: f64,
+    /// 命中的调整档位, 比例控制或未调整时为None
+    pub ctr_tier: Option<usize>,
+    pub total_rate: f64,
+    pub base_value: f64,
+    /// 随机数, 未抽样时为None
+    pub draw: Option<f64>,
+    /// 确定性抽样使用的时间桶
+    pub time_bucket: Option<i64>,
+    /// bandit策略选中的动作及其得分 (后验采样值/UCB/平滑点击率)
+    pub action_id: Option<String>,
+    pub action_score: Option<f64>,
+}
+
+/// 事件类型, 顺序与 `request_fill_show_click` 编码一致
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventType {
+    Request,
+    Fill,
+    Show,
+    Click,
+}
+
+impl EventType {
+    pub fn index(&self) -> usize {
+        match self {
+            EventType::Request => 0,
+            EventType::Fill => 1,
+            EventType::Show => 2,
+            EventType::Click => 3,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EventRequest {
+    pub usr: String,
+    pub ad_id: i64,
+    pub event: EventType,
+    /// 点击是否为诱导/误点, 仅对click事件有效
+    pub temptation: Option<bool>,
+    /// 预估时返回的动作id, 用于更新bandit策略的后验
+    #[serde(default)]
+    pub action_id: Option<String>,
+}
+
+impl EventRequest {
+    pub fn is_tempt_click(&self) -> bool {
+        self.event == EventType::Click && self.temptation.unwrap_or(false)
+    }
+
+    pub fn check(&self) -> (bool, String) {
+        if self.usr.is_empty() {
+            return (false, "用户账号不能为空".to_string());
+        } else if self.ad_id == 0 {
+            return (false, "广告ID不能为空".to_string());
+        }
+        (true, "".to_string())
     }
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchEventRequest {
+    pub events: Vec<EventRequest>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EventResponse {
+    pub code: i32,
+    pub msg: String,
+    pub accepted: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct AdEvent {
     pub request: i64,
     pub fill: i64,
@@ -50,6 +212,27 @@ pub struct AdEvent {
 }
 
 impl AdEvent {
+    /// 解析 `request_fill_show_click` 格式的计数
+    pub fn parse(s: &str) -> Option<AdEvent> {
+        let fields: Vec<_> = s.split("_").collect();
+        if fields.len() < 4 {
+            return None;
+        }
+        Some(AdEvent {
+            request: fields[0].parse().unwrap_or_default(),
+            fill: fields[1].parse().unwrap_or_default(),
+            show: fields[2].parse().unwrap_or_default(),
+            click: fields[3].parse().unwrap_or_default(),
+        })
+    }
+
+    pub fn merge(&mut self, other: &AdEvent) {
+        self.request += other.request;
+        self.fill += other.fill;
+        self.show += other.show;
+        self.click += other.click;
+    }
+
     pub fn get_fill_rate(&self, ab_params: &AbParams) -> f64 {
         (self.fill as f64 + ab_params.fill_a) / ((self.request + 1) as f64 + ab_params.fill_b)
     }
@@ -67,6 +250,30 @@ impl AdEvent {
     }
 }
 
+/// 单个广告预估所需的全部计数与实验配置
+#[derive(Debug, Clone, Default)]
+pub struct AdSignals {
+    pub ad_id: i64,
+    pub realtime_event: AdEvent,
+    pub daily_event: AdEvent,
+    pub exp_cfg: AdIdExpCfg,
+}
+
+/// 实验报告查询参数
+#[derive(Debug, Deserialize)]
+pub struct ReportQuery {
+    /// 每组最小样本数, 默认为实验驱动的min_requests
+    pub min_samples: Option<i64>,
+}
+
+/// 配置变更查询参数
+#[derive(Debug, Deserialize)]
+pub struct CfgChangesQuery {
+    pub key: Option<String>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct AbParams {
     pub fill_a: f64,
     pub fill_b: f64,
@@ -76,7 +283,7 @@ pub struct AbParams {
     pub click_b: f64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ExpBaseCfg {
     pub version: String,
     pub base_value: f64,
@@ -84,7 +291,95 @@ pub struct ExpBaseCfg {
     pub start_time: DateTime<Local>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Default for ExpBaseCfg {
+    fn default() -> Self {
+        Self {
+            version: "".to_string(),
+            base_value: 0.0,
+            score_factor: 1.0,
+            start_time: Local::now(),
+        }
+    }
+}
+
+/// 抽样方式
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DrawMode {
+    /// 每次请求独立随机
+    Random,
+    /// 由 (用户, 广告, 实验版本, 时间桶, 请求id) 的hash得到, 可重放
+    Seeded,
+}
+
+/// 预估配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PredictCfg {
+    pub draw: DrawMode,
+    /// 确定性抽样的时间桶长度, 同一个桶内同样的输入得到同样的结果
+    pub draw_bucket_secs: i64,
+}
+
+impl Default for PredictCfg {
+    fn default() -> Self {
+        Self {
+            draw: DrawMode::Random,
+            draw_bucket_secs: 3600,
+        }
+    }
+}
+
+/// 影子策略配置
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ShadowCfg {
+    /// 执行影子策略的请求比例
+    pub rate: f64,
+    /// 影子策略名, 与线上策略相同的会被跳过
+    pub strategies: Vec<String>,
+}
+
+/// bandit策略参数
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BanditCfg {
+    /// UCB1的探索系数
+    pub ucb_c: f64,
+    /// epsilon-greedy的随机探索概率
+    pub epsilon: f64,
+}
+
+impl Default for BanditCfg {
+    fn default() -> Self {
+        Self {
+            ucb_c: std::f64::consts::SQRT_2,
+            epsilon: 0.1,
+        }
+    }
+}
+
+/// 实验自动评估配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExpDriverCfg {
+    /// 对照组与实验组都达到该请求量后才做判断
+    pub min_requests: i64,
+    /// 新版本实验使用的对照组/实验组
+    pub cg_user: String,
+    pub eg_user: String,
+    /// 版本开始超过该秒数仍样本不足的实验按主动作胜出结束, 应小于版本数据的过期时间(5天)
+    pub max_age_secs: i64,
+}
+
+impl Default for ExpDriverCfg {
+    fn default() -> Self {
+        Self {
+            min_requests: 1000,
+            cg_user: "0".to_string(),
+            eg_user: "1".to_string(),
+            max_age_secs: 3 * 24 * 3600,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct RangeValue {
     pub min: f64,
     pub max: f64,
@@ -127,8 +422,11 @@ mod tests {
             usr: "i123".to_string(),
             ad_id: vec![1, 2, 3],
             service_type: 1,
-            model: "".to_string(),
-            is_debug: false,
+            model: None,
+            is_debug: Some(false),
+            request_id: None,
+            with_scores: None,
+            top_k: None,
         };
 
         println!("Creating new request {:?}", req);
@@ -170,4 +468,41 @@ mod tests {
         let cfg1: AdIdExpCfg = serde_json::from_str(cfg_str.as_str()).unwrap();
         println!("deserde json => {:?}", cfg1)
     }
+
+    #[test]
+    fn test_event_request() {
+        let req: EventRequest =
+            serde_json::from_str(r#"{"usr":"i123","ad_id":12,"event":"click"}"#).unwrap();
+        assert_eq!(req.event, EventType::Click);
+        assert_eq!(req.event.index(), 3);
+        assert!(req.check().0);
+        assert!(!req.is_tempt_click());
+
+        let req: EventRequest =
+            serde_json::from_str(r#"{"usr":"i123","ad_id":12,"event":"click","temptation":true}"#)
+                .unwrap();
+        assert!(req.is_tempt_click());
+
+        let bad = serde_json::from_str::<EventRequest>(r#"{"usr":"i123","ad_id":12,"event":"x"}"#);
+        assert!(bad.is_err());
+    }
+
+    #[test]
+    fn test_parse_ad_event() {
+        let mut event = AdEvent::parse("10_8_5_1").unwrap();
+        assert_eq!(event.request, 10);
+        assert_eq!(event.click, 1);
+        assert!(AdEvent::parse("10_8").is_none());
+
+        event.merge(&AdEvent::parse("1_1_1_x").unwrap());
+        assert_eq!(
+            event,
+            AdEvent {
+                request: 11,
+                fill: 9,
+                show: 6,
+                click: 1
+            }
+        );
+    }
 }
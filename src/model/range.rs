@@ -0,0 +1,356 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, bail, Result};
+
+use super::RangeValue;
+
+/// 区间端点的开闭
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Bounds {
+    /// [min, max)
+    #[default]
+    LeftClosed,
+    /// (min, max]
+    RightClosed,
+    /// [min, max]
+    Closed,
+    /// (min, max)
+    Open,
+}
+
+impl Bounds {
+    pub fn parse(s: &str) -> Result<Self> {
+        match s.trim() {
+            "[)" => Ok(Bounds::LeftClosed),
+            "(]" => Ok(Bounds::RightClosed),
+            "[]" => Ok(Bounds::Closed),
+            "()" => Ok(Bounds::Open),
+            other => bail!("bounds={:?}, expect one of [) (] [] ()", other),
+        }
+    }
+
+    fn min_closed(&self) -> bool {
+        matches!(self, Bounds::LeftClosed | Bounds::Closed)
+    }
+
+    fn max_closed(&self) -> bool {
+        matches!(self, Bounds::RightClosed | Bounds::Closed)
+    }
+
+    pub fn contains(&self, range: &RangeValue, target: f64) -> bool {
+        let above_min = if self.min_closed() {
+            target >= range.min
+        } else {
+            target > range.min
+        };
+        let below_max = if self.max_closed() {
+            target <= range.max
+        } else {
+            target < range.max
+        };
+        above_min && below_max
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Interpolation {
+    /// 取所在区间的值
+    #[default]
+    None,
+    /// 在相邻区间的锚点(中点, 无界区间取有界的一端)之间线性插值
+    Linear,
+}
+
+impl Interpolation {
+    pub fn parse(s: &str) -> Result<Self> {
+        match s.trim() {
+            "" | "none" => Ok(Interpolation::None),
+            "linear" => Ok(Interpolation::Linear),
+            other => bail!("interpolate={:?}, expect none or linear", other),
+        }
+    }
+}
+
+/// 分段查找表, 区间按min升序且互不重叠
+///
+/// 配置为hash: `min_max => value` 定义区间, `inf`/`-inf` 表示无界, 如 `10_inf`;
+/// 不含 `_` 的字段为选项: `bounds` 区间开闭 (默认 `[)`), `default` 未命中时的值 (默认0),
+/// `interpolate` 插值方式 (`none`/`linear`)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RangeTable {
+    ranges: Vec<RangeValue>,
+    bounds: Bounds,
+    default: f64,
+    interpolation: Interpolation,
+    /// 相邻区间之间的空隙 (prev.max, next.min)
+    gaps: Vec<(f64, f64)>,
+}
+
+impl RangeTable {
+    /// ranges已排序且互不重叠
+    fn build(ranges: Vec<RangeValue>, bounds: Bounds) -> Self {
+        let gaps = ranges
+            .windows(2)
+            .filter(|pair| pair[1].min > pair[0].max)
+            .map(|pair| (pair[0].max, pair[1].min))
+            .collect();
+        Self {
+            ranges,
+            bounds,
+            gaps,
+            ..Default::default()
+        }
+    }
+
+    /// 从hash解析, 格式错误的项、未知选项、空区间和与前面区间重叠的区间跳过, 返回表及跳过的原因
+    pub fn parse(hash: &BTreeMap<String, String>) -> (Self, Vec<String>) {
+        let mut ranges = Vec::with_capacity(hash.len());
+        let mut bounds = Bounds::default();
+        let mut default = 0.0;
+        let mut interpolation = Interpolation::default();
+        let mut skipped = Vec::new();
+
+        for (k, v) in hash {
+            let parsed = match k.split_once('_') {
+                Some((min, max)) => parse_range(k, min, max, v).map(|r| ranges.push(r)),
+                None => match k.as_str() {
+                    "bounds" => Bounds::parse(v).map(|b| bounds = b),
+                    "default" => parse_f64(k, v).map(|d| default = d),
+                    "interpolate" => Interpolation::parse(v).map(|i| interpolation = i),
+                    _ => Err(anyhow!("unknown option {}", k)),
+                },
+            };
+            if let Err(e) = parsed {
+                skipped.push(e.to_string());
+            }
+        }
+
+        // 区间是否为空取决于bounds, 需在所有选项解析后检查
+        ranges.retain(|r| match check_range(r, bounds) {
+            Ok(()) => true,
+            Err(e) => {
+                skipped.push(e.to_string());
+                false
+            }
+        });
+        ranges.sort_by(|a, b| a.min.total_cmp(&b.min).then(a.max.total_cmp(&b.max)));
+        let mut kept: Vec<RangeValue> = Vec::with_capacity(ranges.len());
+        for r in ranges {
+            match kept.last() {
+                Some(prev) if overlaps(prev, &r, bounds) => skipped.push(format!(
+                    "range {}_{} overlaps {}_{}",
+                    r.min, r.max, prev.min, prev.max
+                )),
+                _ => kept.push(r),
+            }
+        }
+
+        let table = Self::build(kept, bounds)
+            .with_default(default)
+            .with_interpolation(interpolation);
+        (table, skipped)
+    }
+
+    pub fn with_default(mut self, default: f64) -> Self {
+        self.default = default;
+        self
+    }
+
+    pub fn with_interpolation(mut self, interpolation: Interpolation) -> Self {
+        self.interpolation = interpolation;
+        self
+    }
+
+    pub fn ranges(&self) -> &[RangeValue] {
+        &self.ranges
+    }
+
+    pub fn gaps(&self) -> &[(f64, f64)] {
+        &self.gaps
+    }
+
+    /// 包含target的区间下标
+    fn position(&self, target: f64) -> Option<usize> {
+        // 区间不重叠, 只有min不大于target的最后两个区间可能包含target
+        let end = self.ranges.partition_point(|r| r.min <= target);
+        (end.saturating_sub(2)..end)
+            .rev()
+            .find(|&i| self.bounds.contains(&self.ranges[i], target))
+    }
+
+    /// 包含target的区间
+    pub fn find(&self, target: f64) -> Option<&RangeValue> {
+        self.position(target).map(|i| &self.ranges[i])
+    }
+
+    /// 查找target对应的值, 没有命中区间时返回默认值
+    pub fn lookup(&self, target: f64) -> f64 {
+        let i = match self.position(target) {
+            Some(i) => i,
+            None => return self.default,
+        };
+        match self.interpolation {
+            Interpolation::None => self.ranges[i].value,
+            Interpolation::Linear => self.interpolate(i, target),
+        }
+    }
+
+    fn interpolate(&self, i: usize, target: f64) -> f64 {
+        let cur = &self.ranges[i];
+        let a = anchor(cur);
+        let neighbor = if target < a {
+            i.checked_sub(1).map(|j| &self.ranges[j])
+        } else {
+            self.ranges.get(i + 1)
+        };
+        // 与相邻区间之间有空隙时不跨越插值
+        let neighbor = match neighbor {
+            Some(n) if n.max == cur.min || n.min == cur.max => n,
+            _ => return cur.value,
+        };
+        let b = anchor(neighbor);
+        if !a.is_finite() || !b.is_finite() || a == b {
+            return cur.value;
+        }
+        let t = (target - a) / (b - a);
+        cur.value + (neighbor.value - cur.value) * t
+    }
+}
+
+/// 插值锚点: 有界区间取中点, 单侧无界取有界的一端
+fn anchor(r: &RangeValue) -> f64 {
+    match (r.min.is_finite(), r.max.is_finite()) {
+        (true, true) => (r.min + r.max) / 2.0,
+        (true, false) => r.min,
+        (false, true) => r.max,
+        (false, false) => 0.0,
+    }
+}
+
+fn parse_range(key: &str, min: &str, max: &str, value: &str) -> Result<RangeValue> {
+    Ok(RangeValue {
+        min: parse_f64(key, min)?,
+        max: parse_f64(key, max)?,
+        value: parse_f64(key, value)?,
+    })
+}
+
+fn check_range(r: &RangeValue, bounds: Bounds) -> Result<()> {
+    if r.min.is_nan() || r.max.is_nan() || !r.value.is_finite() {
+        bail!("invalid range {:?}", r);
+    }
+    if r.min > r.max || (r.min == r.max && bounds != Bounds::Closed) {
+        bail!("empty range {}_{}", r.min, r.max);
+    }
+    Ok(())
+}
+
+/// next.min不小于prev.min时两个区间是否重叠, 闭区间在端点相接也算重叠
+fn overlaps(prev: &RangeValue, next: &RangeValue, bounds: Bounds) -> bool {
+    next.min < prev.max || (next.min == prev.max && bounds == Bounds::Closed)
+}
+
+fn parse_f64(key: &str, s: &str) -> Result<f64> {
+    let v: f64 = s
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("{}: {:?} is not a number", key, s))?;
+    if v.is_nan() {
+        bail!("{}: NaN is not allowed", key);
+    }
+    Ok(v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::hash;
+
+    fn parse(pairs: &[(&str, &str)]) -> RangeTable {
+        let (table, skipped) = RangeTable::parse(&hash(pairs));
+        assert!(skipped.is_empty(), "{:?}", skipped);
+        table
+    }
+
+    #[test]
+    fn test_left_closed_lookup() {
+        let table = parse(&[
+            ("0.5_1", "3"),
+            ("0_0.2", "1"),
+            ("0.2_0.5", "2"),
+            ("10_inf", "4"),
+        ]);
+        assert_eq!(table.lookup(-1.0), 0.0);
+        assert_eq!(table.lookup(0.0), 1.0);
+        assert_eq!(table.lookup(0.1), 1.0);
+        assert_eq!(table.lookup(0.2), 2.0);
+        assert_eq!(table.lookup(0.99), 3.0);
+        assert_eq!(table.lookup(1.0), 0.0);
+        assert_eq!(table.lookup(1e9), 4.0);
+        assert_eq!(table.gaps(), &[(1.0, 10.0)]);
+    }
+
+    #[test]
+    fn test_bounds_and_default() {
+        let table = parse(&[
+            ("-inf_0", "1"),
+            ("0_1", "2"),
+            ("bounds", "(]"),
+            ("default", "0.5"),
+        ]);
+        assert_eq!(table.lookup(0.0), 1.0);
+        assert_eq!(table.lookup(1.0), 2.0);
+        assert_eq!(table.lookup(1.5), 0.5);
+
+        let table = parse(&[("0_1", "2"), ("bounds", "()")]);
+        assert_eq!(table.lookup(0.0), 0.0);
+        assert_eq!(table.lookup(0.5), 2.0);
+    }
+
+    #[test]
+    fn test_invalid_entries_skipped() {
+        // 区间重叠时保留靠前的区间
+        let (table, skipped) =
+            RangeTable::parse(&hash(&[("0_2", "1"), ("1_3", "2"), ("3_4", "3")]));
+        assert_eq!(skipped, vec!["range 1_3 overlaps 0_2"]);
+        assert_eq!(table.lookup(1.5), 1.0);
+        assert_eq!(table.lookup(3.5), 3.0);
+
+        // 闭区间在端点重叠
+        let (table, skipped) =
+            RangeTable::parse(&hash(&[("0_1", "1"), ("1_2", "2"), ("bounds", "[]")]));
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(table.lookup(1.0), 1.0);
+
+        // 格式错误、空区间、未知选项和不合法的选项值都跳过, 其余项照常生效
+        let (table, skipped) = RangeTable::parse(&hash(&[
+            ("0_1", "1"),
+            ("2_1", "1"),
+            ("0_x", "1"),
+            ("bounds", "[["),
+            ("note", "legacy"),
+            ("default", "0.5"),
+        ]));
+        assert_eq!(skipped.len(), 4);
+        assert_eq!(table.lookup(0.0), 1.0);
+        assert_eq!(table.lookup(1.0), 0.5);
+    }
+
+    #[test]
+    fn test_linear_interpolation() {
+        let table = parse(&[
+            ("0_1", "1"),
+            ("1_2", "3"),
+            ("5_inf", "10"),
+            ("interpolate", "linear"),
+        ]);
+        // 锚点 0.5 -> 1, 1.5 -> 3
+        assert_eq!(table.lookup(0.5), 1.0);
+        assert_eq!(table.lookup(1.0), 2.0);
+        assert_eq!(table.lookup(1.25), 2.5);
+        assert_eq!(table.lookup(0.2), 1.0);
+        // 与下一个区间之间有空隙, 不插值
+        assert_eq!(table.lookup(1.9), 3.0);
+        assert_eq!(table.lookup(7.0), 10.0);
+    }
+}
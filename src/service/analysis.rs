@@ -0,0 +1,349 @@
+use serde::Serialize;
+
+use crate::dao::*;
+use crate::model::*;
+
+/// 95% 置信区间对应的正态分位数
+pub const Z_95: f64 = 1.959963984540054;
+/// 显著性水平
+pub const SIGNIFICANCE: f64 = 0.05;
+
+/// 一个比例指标: successes / trials, 附 Wilson 95% 置信区间
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RateStat {
+    pub successes: i64,
+    pub trials: i64,
+    pub rate: f64,
+    pub ci_low: f64,
+    pub ci_high: f64,
+}
+
+impl RateStat {
+    pub fn new(successes: i64, trials: i64) -> Self {
+        let (successes, trials) = (successes.max(0), trials.max(0));
+        if trials == 0 {
+            return Self {
+                successes,
+                trials,
+                rate: 0.0,
+                ci_low: 0.0,
+                ci_high: 1.0,
+            };
+        }
+        let n = trials as f64;
+        let p = (successes as f64 / n).min(1.0);
+        let z2 = Z_95 * Z_95;
+        let center = (p + z2 / (2.0 * n)) / (1.0 + z2 / n);
+        let half = Z_95 * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt() / (1.0 + z2 / n);
+        Self {
+            successes,
+            trials,
+            rate: p,
+            // p为0或1时区间端点理论上恰为0或1, 避免浮点残差
+            ci_low: if successes == 0 {
+                0.0
+            } else {
+                (center - half).max(0.0)
+            },
+            ci_high: if successes >= trials {
+                1.0
+            } else {
+                (center + half).min(1.0)
+            },
+        }
+    }
+}
+
+/// 对照组与实验组的同一指标对比
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RateComparison {
+    pub control: RateStat,
+    pub experiment: RateStat,
+    /// 实验组相对对照组的提升, 对照组为0时为None
+    pub lift: Option<f64>,
+    /// 双比例z检验, 样本为0或两组全部成功/失败时为None
+    pub z: Option<f64>,
+    pub p_value: Option<f64>,
+    pub significant: bool,
+    /// 两组样本数都不低于最小样本数
+    pub sufficient: bool,
+}
+
+impl RateComparison {
+    pub fn new(control: RateStat, experiment: RateStat, min_samples: i64) -> Self {
+        let test = two_proportion_z_test(
+            control.successes,
+            control.trials,
+            experiment.successes,
+            experiment.trials,
+        );
+        let lift = (control.rate > 0.0).then(|| experiment.rate / control.rate - 1.0);
+        let sufficient = control.trials >= min_samples && experiment.trials >= min_samples;
+        Self {
+            lift,
+            z: test.map(|(z, _)| z),
+            p_value: test.map(|(_, p)| p),
+            significant: sufficient && test.is_some_and(|(_, p)| p < SIGNIFICANCE),
+            sufficient,
+            control,
+            experiment,
+        }
+    }
+}
+
+/// 漏斗各环节: 填充率 fill/request, 展示率 show/fill, 点击率 click/show
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FunnelComparison {
+    pub fill_rate: RateComparison,
+    pub show_rate: RateComparison,
+    pub ctr: RateComparison,
+}
+
+impl FunnelComparison {
+    pub fn new(cg: &AdEvent, eg: &AdEvent, min_samples: i64) -> Self {
+        Self {
+            fill_rate: RateComparison::new(
+                RateStat::new(cg.fill, cg.request),
+                RateStat::new(eg.fill, eg.request),
+                min_samples,
+            ),
+            show_rate: RateComparison::new(
+                RateStat::new(cg.show, cg.fill),
+                RateStat::new(eg.show, eg.fill),
+                min_samples,
+            ),
+            ctr: RateComparison::new(
+                RateStat::new(cg.click, cg.show),
+                RateStat::new(eg.click, eg.show),
+                min_samples,
+            ),
+        }
+    }
+
+    /// 样本不足的指标
+    fn warnings(&self, scope: &str, min_samples: i64) -> Vec<String> {
+        [
+            ("fill_rate", "requests", &self.fill_rate),
+            ("show_rate", "fills", &self.show_rate),
+            ("ctr", "shows", &self.ctr),
+        ]
+        .iter()
+        .filter(|(_, _, c)| !c.sufficient)
+        .map(|(metric, unit, c)| {
+            format!(
+                "{} {}: control {} / experiment {} {} < min_samples {}",
+                scope, metric, c.control.trials, c.experiment.trials, unit, min_samples
+            )
+        })
+        .collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AdReport {
+    pub ad_id: i64,
+    pub cg_user: String,
+    pub eg_user: String,
+    pub main_action_id: String,
+    pub eg_action_id: String,
+    #[serde(flatten)]
+    pub funnel: FunnelComparison,
+}
+
+/// 实验版本的分析报告
+#[derive(Debug, Clone, Serialize)]
+pub struct ExperimentReport {
+    pub version: String,
+    pub min_samples: i64,
+    pub ads: Vec<AdReport>,
+    /// 所有广告计数合并后的对比
+    pub aggregate: FunnelComparison,
+    pub warnings: Vec<String>,
+}
+
+/// 双比例z检验 (合并方差), 返回 (z, 双侧p值)
+pub fn two_proportion_z_test(s1: i64, n1: i64, s2: i64, n2: i64) -> Option<(f64, f64)> {
+    if n1 <= 0 || n2 <= 0 {
+        return None;
+    }
+    let (n1, n2) = (n1 as f64, n2 as f64);
+    let (p1, p2) = (s1.max(0) as f64 / n1, s2.max(0) as f64 / n2);
+    let pooled = (s1.max(0) + s2.max(0)) as f64 / (n1 + n2);
+    let se = (pooled * (1.0 - pooled) * (1.0 / n1 + 1.0 / n2)).sqrt();
+    if se.is_nan() || se <= 0.0 {
+        return None;
+    }
+    let z = (p2 - p1) / se;
+    Some((z, 2.0 * (1.0 - normal_cdf(z.abs()))))
+}
+
+fn normal_cdf(x: f64) -> f64 {
+    0.5 * (1.0 + erf(x / std::f64::consts::SQRT_2))
+}
+
+/// Abramowitz-Stegun 7.1.26, 误差小于1.5e-7
+fn erf(x: f64) -> f64 {
+    let sign = if x < 0.0 { -1.0 } else { 1.0 };
+    let x = x.abs();
+    let t = 1.0 / (1.0 + 0.3275911 * x);
+    let y = 1.0
+        - (((((1.061405429 * t - 1.453152027) * t) + 1.421413741) * t - 0.284496736) * t
+            + 0.254829592)
+            * t
+            * (-x * x).exp();
+    sign * y
+}
+
+/// 实验分析: 从实验分组计数生成对照组与实验组的对比报告
+#[derive(Clone)]
+pub struct AnalysisService {
+    ads_dao: AdsDB,
+}
+
+impl AnalysisService {
+    pub fn new(ads_dao: AdsDB) -> Self {
+        Self { ads_dao }
+    }
+
+    /// min_samples默认为实验驱动的min_requests
+    pub async fn report(&self, version: &str, min_samples: Option<i64>) -> ExperimentReport {
+        let min_samples =
+            min_samples.unwrap_or_else(|| self.ads_dao.get_exp_driver_cfg().min_requests);
+        let mut ads = Vec::new();
+        let mut warnings = Vec::new();
+        let (mut cg_total, mut eg_total) = (AdEvent::default(), AdEvent::default());
+
+        for ad_id in self.ads_dao.get_version_adids(version).await {
+            let cfg = self.ads_dao.get_adid_exp_cfg(version, ad_id).await;
+            if cfg.is_empty() {
+                warnings.push(format!("ad {}: no experiment config, skipped", ad_id));
+                continue;
+            }
+            let cg = self
+                .ads_dao
+                .get_exp_group_event(version, &cfg.cg_user, ad_id)
+                .await;
+            let eg = self
+                .ads_dao
+                .get_exp_group_event(version, &cfg.eg_user, ad_id)
+                .await;
+            cg_total.merge(&cg);
+            eg_total.merge(&eg);
+
+            let funnel = FunnelComparison::new(&cg, &eg, min_samples);
+            warnings.extend(funnel.warnings(&format!("ad {}", ad_id), min_samples));
+            ads.push(AdReport {
+                ad_id,
+                cg_user: cfg.cg_user,
+                eg_user: cfg.eg_user,
+                main_action_id: cfg.main_action_id,
+                eg_action_id: cfg.eg_action_id,
+                funnel,
+            });
+        }
+
+        let aggregate = FunnelComparison::new(&cg_total, &eg_total, min_samples);
+        warnings.extend(aggregate.warnings("aggregate", min_samples));
+        ExperimentReport {
+            version: version.to_string(),
+            min_samples,
+            ads,
+            aggregate,
+            warnings,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    fn approx(a: f64, b: f64, eps: f64) -> bool {
+        (a - b).abs() < eps
+    }
+
+    #[test]
+    fn test_wilson_interval() {
+        let stat = RateStat::new(10, 100);
+        assert_eq!(stat.rate, 0.1);
+        assert!(approx(stat.ci_low, 0.0552, 1e-4));
+        assert!(approx(stat.ci_high, 0.1744, 1e-4));
+
+        let stat = RateStat::new(0, 0);
+        assert_eq!((stat.ci_low, stat.ci_high), (0.0, 1.0));
+        let stat = RateStat::new(0, 50);
+        assert_eq!(stat.ci_low, 0.0);
+        assert!(stat.ci_high > 0.0);
+    }
+
+    #[test]
+    fn test_two_proportion_z_test() {
+        let (z, p) = two_proportion_z_test(200, 1000, 250, 1000).unwrap();
+        assert!(approx(z, 2.6774, 1e-3));
+        assert!(approx(p, 0.00742, 1e-4));
+
+        let (z, p) = two_proportion_z_test(250, 1000, 200, 1000).unwrap();
+        assert!(z < 0.0);
+        assert!(approx(p, 0.00742, 1e-4));
+
+        assert!(two_proportion_z_test(0, 0, 1, 10).is_none());
+        assert!(two_proportion_z_test(0, 10, 0, 10).is_none());
+        assert!(approx(normal_cdf(0.0), 0.5, 1e-7));
+        assert!(approx(normal_cdf(1.959963984540054), 0.975, 1e-6));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_experiment_report() {
+        let storage = Arc::new(MemoryStorage::new());
+        let ads_db = AdsDB::new(storage.clone()).await;
+        for ad_id in [1, 2] {
+            ads_db
+                .set_adid_exp_cfg(
+                    "1",
+                    ad_id,
+                    AdIdExpCfg {
+                        ad_id,
+                        version: "1".to_string(),
+                        cg_user: "0".to_string(),
+                        eg_user: "1".to_string(),
+                        eg_action_id: "b".to_string(),
+                        main_action_id: "a".to_string(),
+                        exp_action_value: 0.2,
+                        main_action_value: 0.1,
+                    },
+                )
+                .await;
+        }
+        ads_db.update_adids("1", vec![1, 2, 3]).await;
+        for (key, value) in [
+            ("expversion:event:1:0:1", "2000_1800_1000_200"),
+            ("expversion:event:1:1:1", "2000_1800_1000_250"),
+            ("expversion:event:1:0:2", "20_10_5_1"),
+        ] {
+            storage.set(key, value, None).await.unwrap();
+        }
+
+        let report = AnalysisService::new(ads_db).report("1", Some(100)).await;
+        assert_eq!(report.ads.len(), 2);
+        let ad = &report.ads[0];
+        assert_eq!(ad.ad_id, 1);
+        assert_eq!(ad.funnel.ctr.control.rate, 0.2);
+        assert!(ad.funnel.ctr.significant);
+        assert!(!ad.funnel.fill_rate.significant);
+        assert!(approx(ad.funnel.ctr.lift.unwrap(), 0.25, 1e-9));
+
+        // 广告2样本不足
+        assert!(!report.ads[1].funnel.ctr.sufficient);
+        assert!(!report.ads[1].funnel.ctr.significant);
+        assert_eq!(report.aggregate.ctr.control.trials, 1005);
+        assert_eq!(report.aggregate.ctr.experiment.successes, 250);
+        assert!(report.warnings.iter().any(|w| w.starts_with("ad 2 ctr")));
+        assert!(report.warnings.iter().any(|w| w.starts_with("ad 3")));
+        assert!(!report.warnings.iter().any(|w| w.starts_with("aggregate")));
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert!(json["ads"][0]["ctr"]["p_value"].is_number());
+    }
+}
@@ -0,0 +1,32 @@
+use crate::dao::*;
+use crate::model::*;
+
+#[derive(Clone)]
+pub struct EventService {
+    ads_dao: AdsDB,
+}
+
+impl EventService {
+    pub fn new(ads_dao: AdsDB) -> Self {
+        Self { ads_dao }
+    }
+
+    /// 写入事件计数, 返回写入的事件数
+    pub async fn track(&self, events: &[EventRequest]) -> anyhow::Result<usize> {
+        let version = self.ads_dao.get_exp_base_cfg().version;
+        let bucketing = self.ads_dao.get_bucketing_cfg();
+        let usergroups: Vec<String> = events
+            .iter()
+            .map(|e| super::get_exp_usergroup(&bucketing, &version, &e.usr))
+            .collect();
+        let items: Vec<(&EventRequest, &str)> = events
+            .iter()
+            .zip(usergroups.iter())
+            .map(|(e, ug)| (e, ug.as_str()))
+            .collect();
+
+        self.ads_dao.record_events(&items).await?;
+        metrics::counter!("ad_events_total", events.len() as u64);
+        Ok(events.len())
+    }
+}
@@ -0,0 +1,621 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::Local;
+use tokio_cron_scheduler::{Job, JobScheduler};
+
+use super::LeaderElector;
+use crate::dao::*;
+use crate::model::*;
+
+/// 动作得分 = 点击/展示 * SCORE_SCALE, 与动作的目标CTR口径一致
+const SCORE_SCALE: f64 = 1_000_000.0;
+pub const EXP_DRIVER_CRON: &str = "0 */5 * * * *";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExpDecision {
+    /// 样本不足, 继续实验
+    Pending,
+    /// 实验动作胜出, 提升为主动作
+    Promote,
+    /// 主动作胜出
+    Keep,
+}
+
+/// 一轮评估的结果
+#[derive(Debug, Default)]
+pub struct ExpRound {
+    pub promoted: Vec<i64>,
+    pub kept: Vec<i64>,
+    pub pending: Vec<i64>,
+    /// 超过max_age_secs仍样本不足, 按主动作胜出结束
+    pub expired: Vec<i64>,
+    /// 运行中开启/关闭了分桶, 分组标签被改写
+    pub relabeled: Vec<i64>,
+    pub new_version: Option<String>,
+}
+
+pub fn action_score(event: &AdEvent) -> i64 {
+    if event.show <= 0 {
+        return 0;
+    }
+    (event.click as f64 / event.show as f64 * SCORE_SCALE) as i64
+}
+
+/// 实验组得分乘以score_factor后仍高于对照组时, 实验动作胜出
+pub fn evaluate(cg: &AdEvent, eg: &AdEvent, score_factor: f64, min_requests: i64) -> ExpDecision {
+    if cg.request < min_requests || eg.request < min_requests {
+        return ExpDecision::Pending;
+    }
+    if action_score(eg) as f64 * score_factor > action_score(cg) as f64 {
+        ExpDecision::Promote
+    } else {
+        ExpDecision::Keep
+    }
+}
+
+/// 下一个实验动作: 按action_id顺序轮转, 跳过主动作
+pub fn next_exp_action(
+    actions: &BTreeMap<String, f64>,
+    main_action_id: &str,
+    last_exp_action_id: &str,
+) -> Option<(String, f64)> {
+    let candidates: Vec<(&String, &f64)> = actions
+        .iter()
+        .filter(|(id, _)| id.as_str() != main_action_id)
+        .collect();
+    if candidates.is_empty() {
+        return None;
+    }
+    let pos = candidates
+        .iter()
+        .position(|(id, _)| id.as_str() > last_exp_action_id)
+        .unwrap_or(0);
+    Some((candidates[pos].0.clone(), *candidates[pos].1))
+}
+
+/// 版本号最后一段加一, 如 1.0.9 -> 1.0.10, 非数字版本追加 .1
+pub fn next_version(version: &str) -> String {
+    let (prefix, last) = match version.rfind('.') {
+        Some(pos) => (&version[..pos + 1], &version[pos + 1..]),
+        None => ("", version),
+    };
+    match last.parse::<i64>() {
+        Ok(v) => format!("{}{}", prefix, v + 1),
+        Err(_) => format!("{}.1", version),
+    }
+}
+
+/// 新版本下广告的实验配置, 没有可选动作时返回None
+pub fn plan_next_cfg(
+    ad_id: i64,
+    version: &str,
+    prev: &AdIdExpCfg,
+    decision: ExpDecision,
+    actions: &BTreeMap<String, f64>,
+    driver_cfg: &ExpDriverCfg,
+) -> Option<AdIdExpCfg> {
+    let (main_action_id, main_action_value) = if prev.is_empty() {
+        let (id, value) = actions.iter().next()?;
+        (id.clone(), *value)
+    } else if decision == ExpDecision::Promote {
+        (prev.eg_action_id.clone(), prev.exp_action_value)
+    } else {
+        (prev.main_action_id.clone(), prev.main_action_value)
+    };
+
+    let (eg_action_id, exp_action_value) =
+        next_exp_action(actions, &main_action_id, &prev.eg_action_id)
+            .unwrap_or_else(|| (main_action_id.clone(), main_action_value));
+
+    Some(AdIdExpCfg {
+        ad_id,
+        version: version.to_string(),
+        cg_user: driver_cfg.cg_user.clone(),
+        eg_user: driver_cfg.eg_user.clone(),
+        eg_action_id,
+        main_action_id,
+        exp_action_value,
+        main_action_value,
+    })
+}
+
+/// 实验驱动: 定时汇总各广告对照组/实验组的表现, 写入动作得分,
+/// 所有实验都有结论(或版本超时)后提升胜出动作并开启新版本实验, 只在leader上执行
+#[derive(Clone)]
+pub struct ExpDriver {
+    ads_dao: AdsDB,
+    leader: LeaderElector,
+    cron: String,
+}
+
+impl ExpDriver {
+    pub fn new(ads_dao: AdsDB, leader: LeaderElector) -> Self {
+        Self {
+            ads_dao,
+            leader,
+            cron: EXP_DRIVER_CRON.to_string(),
+        }
+    }
+
+    pub fn with_cron(mut self, cron: &str) -> Self {
+        self.cron = cron.to_string();
+        self
+    }
+
+    pub fn start(&self) {
+        log::info!("starting experiment driver");
+        let sched = JobScheduler::new().unwrap();
+        let driver = self.clone();
+
+        let _ = sched.add(
+            Job::new_async(self.cron.as_str(), move |_, _| {
+                let driver = driver.clone();
+                Box::pin(async move {
+                    let lease = match driver.leader.lease_value() {
+                        Some(lease) => lease,
+                        None => {
+                            log::debug!("experiment driver skipped, not leader");
+                            return;
+                        }
+                    };
+                    match driver.run_once(&lease).await {
+                        Ok(round) => log::info!("experiment driver round: {:?}", round),
+                        Err(e) => log::error!("experiment driver error: {}", e),
+                    }
+                })
+            })
+            .unwrap(),
+        );
+        sched.start().unwrap();
+    }
+
+    /// 版本的对照组/实验组标签: 配置了分桶时使用分桶的分组, 否则使用cfg:exp:driver的分组
+    fn group_labels(&self, version: &str, driver_cfg: &ExpDriverCfg) -> (String, String) {
+        if self
+            .ads_dao
+            .get_bucketing_cfg()
+            .experiments
+            .contains_key(version)
+        {
+            (
+                Group::Control.label().to_string(),
+                Group::Experiment.label().to_string(),
+            )
+        } else {
+            (driver_cfg.cg_user.clone(), driver_cfg.eg_user.clone())
+        }
+    }
+
+    /// lease为本轮开始时的leader租约, 写入前检查租约仍有效, 切换版本使用fencing写入
+    pub async fn run_once(&self, lease: &str) -> anyhow::Result<ExpRound> {
+        let base_cfg = self.ads_dao.get_exp_base_cfg();
+        let driver_cfg = self.ads_dao.get_exp_driver_cfg();
+        let mut round = ExpRound::default();
+        if base_cfg.version.is_empty() {
+            return Ok(round);
+        }
+        self.leader.check(lease).await?;
+
+        // 版本超时后样本不足的广告不再阻塞切换版本
+        let expired = Local::now().signed_duration_since(base_cfg.start_time)
+            >= chrono::Duration::seconds(driver_cfg.max_age_secs);
+        let (cg_user, eg_user) = self.group_labels(&base_cfg.version, &driver_cfg);
+        let ad_ids = self.ads_dao.get_version_adids(&base_cfg.version).await;
+        let mut plans = Vec::with_capacity(ad_ids.len());
+        for ad_id in ad_ids {
+            let mut cfg = self
+                .ads_dao
+                .get_adid_exp_cfg(&base_cfg.version, ad_id)
+                .await;
+            let actions = self.ads_dao.get_target_ctr_actions(ad_id).await;
+            if cfg.is_empty() {
+                if !actions.is_empty() {
+                    plans.push((ad_id, cfg, ExpDecision::Keep, actions));
+                }
+                continue;
+            }
+            // 运行中开启/关闭分桶后事件按新的分组计数, 广告配置跟随改写, 否则实验组匹配不到用户
+            if cfg.cg_user != cg_user || cfg.eg_user != eg_user {
+                log::warn!(
+                    "experiment version {} ad {} groups {}/{} -> {}/{}, bucketing changed while running",
+                    base_cfg.version,
+                    ad_id,
+                    cfg.cg_user,
+                    cfg.eg_user,
+                    cg_user,
+                    eg_user
+                );
+                cfg.cg_user = cg_user.clone();
+                cfg.eg_user = eg_user.clone();
+                self.ads_dao
+                    .set_adid_exp_cfg(&base_cfg.version, ad_id, cfg.clone())
+                    .await;
+                round.relabeled.push(ad_id);
+            }
+
+            let cg = self
+                .ads_dao
+                .get_exp_group_event(&base_cfg.version, &cfg.cg_user, ad_id)
+                .await;
+            let eg = self
+                .ads_dao
+                .get_exp_group_event(&base_cfg.version, &cfg.eg_user, ad_id)
+                .await;
+            let scores = HashMap::from([
+                (cfg.main_action_id.clone(), action_score(&cg)),
+                (cfg.eg_action_id.clone(), action_score(&eg)),
+            ]);
+            self.ads_dao
+                .set_ad_exp_action_score(&base_cfg.version, ad_id, scores)
+                .await;
+
+            let mut decision = evaluate(&cg, &eg, base_cfg.score_factor, driver_cfg.min_requests);
+            if decision == ExpDecision::Pending && expired {
+                decision = ExpDecision::Keep;
+                round.expired.push(ad_id);
+            }
+            match decision {
+                ExpDecision::Pending => round.pending.push(ad_id),
+                ExpDecision::Promote => round.promoted.push(ad_id),
+                ExpDecision::Keep => round.kept.push(ad_id),
+            }
+            plans.push((ad_id, cfg, decision, actions));
+        }
+
+        // 还有实验未出结论, 或者没有任何可开启的实验
+        if !round.pending.is_empty() || plans.is_empty() {
+            return Ok(round);
+        }
+
+        self.leader.check(lease).await?;
+        let new_version = next_version(&base_cfg.version);
+        let (cg_user, eg_user) = self.group_labels(&new_version, &driver_cfg);
+        let driver_cfg = ExpDriverCfg {
+            cg_user,
+            eg_user,
+            ..driver_cfg
+        };
+        let mut new_ad_ids = Vec::with_capacity(plans.len());
+        for (ad_id, cfg, decision, actions) in plans {
+            if let Some(next_cfg) =
+                plan_next_cfg(ad_id, &new_version, &cfg, decision, &actions, &driver_cfg)
+            {
+                self.ads_dao
+                    .set_adid_exp_cfg(&new_version, ad_id, next_cfg)
+                    .await;
+                new_ad_ids.push(ad_id);
+            }
+        }
+        self.ads_dao.update_adids(&new_version, new_ad_ids).await;
+        let new_base_cfg = ExpBaseCfg {
+            version: new_version.clone(),
+            start_time: Local::now(),
+            ..base_cfg.clone()
+        };
+        if !self
+            .ads_dao
+            .update_exp_base_cfg(&new_base_cfg, lease)
+            .await?
+        {
+            anyhow::bail!(
+                "lease {} lost, experiment version {} not rolled to {}",
+                lease,
+                base_cfg.version,
+                new_version
+            );
+        }
+        log::info!(
+            "experiment version {} -> {}, promoted={:?}, expired={:?}",
+            base_cfg.version,
+            new_version,
+            round.promoted,
+            round.expired
+        );
+
+        round.new_version = Some(new_version);
+        Ok(round)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    fn event(request: i64, click: i64) -> AdEvent {
+        AdEvent {
+            request,
+            fill: request,
+            show: request,
+            click,
+        }
+    }
+
+    fn exp_cfg(ad_id: i64) -> AdIdExpCfg {
+        AdIdExpCfg {
+            ad_id,
+            version: "1".to_string(),
+            cg_user: "0".to_string(),
+            eg_user: "1".to_string(),
+            eg_action_id: "b".to_string(),
+            main_action_id: "a".to_string(),
+            exp_action_value: 0.2,
+            main_action_value: 0.1,
+        }
+    }
+
+    #[test]
+    fn test_action_score_is_ctr() {
+        let e = AdEvent {
+            request: 100,
+            fill: 50,
+            show: 20,
+            click: 2,
+        };
+        assert_eq!(action_score(&e), 100_000);
+        assert_eq!(action_score(&AdEvent::default()), 0);
+    }
+
+    #[test]
+    fn test_evaluate() {
+        assert_eq!(
+            evaluate(&event(10, 1), &event(2000, 100), 0.9, 1000),
+            ExpDecision::Pending
+        );
+        assert_eq!(
+            evaluate(&event(2000, 100), &event(2000, 200), 0.9, 1000),
+            ExpDecision::Promote
+        );
+        // 实验组仅高5%, 乘以0.9后不足以胜出
+        assert_eq!(
+            evaluate(&event(2000, 100), &event(2000, 105), 0.9, 1000),
+            ExpDecision::Keep
+        );
+    }
+
+    #[test]
+    fn test_next_version() {
+        assert_eq!(next_version("1"), "2");
+        assert_eq!(next_version("1.0.9"), "1.0.10");
+        assert_eq!(next_version("v1"), "v1.1");
+    }
+
+    #[test]
+    fn test_next_exp_action() {
+        let actions = BTreeMap::from([
+            ("a".to_string(), 0.1),
+            ("b".to_string(), 0.2),
+            ("c".to_string(), 0.3),
+        ]);
+        assert_eq!(
+            next_exp_action(&actions, "a", "b"),
+            Some(("c".to_string(), 0.3))
+        );
+        assert_eq!(
+            next_exp_action(&actions, "a", "c"),
+            Some(("b".to_string(), 0.2))
+        );
+        let only_main = BTreeMap::from([("a".to_string(), 0.1)]);
+        assert_eq!(next_exp_action(&only_main, "a", ""), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_run_once_promotes_and_starts_new_version() {
+        let storage = Arc::new(MemoryStorage::new());
+        let now = Local::now().format("%Y-%m-%d %H:%M:%S%z").to_string();
+        storage
+            .hset_multiple(
+                "cfg:exp:base",
+                &[
+                    ("version".to_string(), "1".to_string()),
+                    ("score_factor".to_string(), "0.9".to_string()),
+                    ("start_time".to_string(), now),
+                ],
+            )
+            .await
+            .unwrap();
+        storage
+            .hset_multiple(
+                "cfg:exp:driver",
+                &[("min_requests".to_string(), "2".to_string())],
+            )
+            .await
+            .unwrap();
+        storage
+            .hset_multiple(
+                "cfg:exp:action:targetctr:7",
+                &[
+                    ("a".to_string(), "0.1".to_string()),
+                    ("b".to_string(), "0.2".to_string()),
+                    ("c".to_string(), "0.3".to_string()),
+                ],
+            )
+            .await
+            .unwrap();
+        storage
+            .set("expversion:event:1:0:7", "4_4_4_1", None)
+            .await
+            .unwrap();
+        storage
+            .set("expversion:event:1:1:7", "4_4_4_3", None)
+            .await
+            .unwrap();
+
+        let ads_db = AdsDB::new(storage.clone()).await;
+        ads_db.update_adids("1", vec![7]).await;
+        ads_db.set_adid_exp_cfg("1", 7, exp_cfg(7)).await;
+
+        let leader = LeaderElector::new(
+            storage.clone(),
+            "test".to_string(),
+            std::time::Duration::from_secs(10),
+        );
+        assert!(leader.tick().await);
+        let lease = leader.lease_value().unwrap();
+        let driver = ExpDriver::new(ads_db.clone(), leader.clone());
+        // 不是当前租约时不执行
+        assert!(driver.run_once("other#0").await.is_err());
+        let round = driver.run_once(&lease).await.unwrap();
+        assert_eq!(round.promoted, vec![7]);
+        assert_eq!(round.new_version.as_deref(), Some("2"));
+
+        let next_cfg = ads_db.get_adid_exp_cfg("2", 7).await;
+        assert_eq!(next_cfg.main_action_id, "b");
+        assert_eq!(next_cfg.eg_action_id, "c");
+        assert_eq!(ads_db.get_version_adids("2").await, vec![7]);
+
+        let base = storage.hgetall("cfg:exp:base").await.unwrap();
+        assert_eq!(base.get("version").unwrap(), "2");
+    }
+
+    async fn run_with_pending_ad(start_time: &str) -> ExpRound {
+        let storage = Arc::new(MemoryStorage::new());
+        storage
+            .hset_multiple(
+                "cfg:exp:base",
+                &[
+                    ("version".to_string(), "1".to_string()),
+                    ("score_factor".to_string(), "0.9".to_string()),
+                    ("start_time".to_string(), start_time.to_string()),
+                ],
+            )
+            .await
+            .unwrap();
+        storage
+            .hset_multiple(
+                "cfg:exp:driver",
+                &[("min_requests".to_string(), "2".to_string())],
+            )
+            .await
+            .unwrap();
+        for ad_id in [7, 8] {
+            storage
+                .hset_multiple(
+                    &format!("cfg:exp:action:targetctr:{}", ad_id),
+                    &[
+                        ("a".to_string(), "0.1".to_string()),
+                        ("b".to_string(), "0.2".to_string()),
+                        ("c".to_string(), "0.3".to_string()),
+                    ],
+                )
+                .await
+                .unwrap();
+        }
+        // 广告7已出结论, 广告8没有样本
+        storage
+            .set("expversion:event:1:0:7", "4_4_4_1", None)
+            .await
+            .unwrap();
+        storage
+            .set("expversion:event:1:1:7", "4_4_4_3", None)
+            .await
+            .unwrap();
+
+        let ads_db = AdsDB::new(storage.clone()).await;
+        ads_db.update_adids("1", vec![7, 8]).await;
+        ads_db.set_adid_exp_cfg("1", 7, exp_cfg(7)).await;
+        ads_db.set_adid_exp_cfg("1", 8, exp_cfg(8)).await;
+
+        let leader = LeaderElector::new(
+            storage.clone(),
+            "test".to_string(),
+            std::time::Duration::from_secs(10),
+        );
+        assert!(leader.tick().await);
+        let lease = leader.lease_value().unwrap();
+        ExpDriver::new(ads_db, leader)
+            .run_once(&lease)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_run_once_waits_for_pending() {
+        let now = Local::now().format("%Y-%m-%d %H:%M:%S%z").to_string();
+        let round = run_with_pending_ad(&now).await;
+        assert_eq!(round.promoted, vec![7]);
+        assert_eq!(round.pending, vec![8]);
+        assert!(round.expired.is_empty());
+        assert_eq!(round.new_version, None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_run_once_expires_pending() {
+        let round = run_with_pending_ad("2022-05-30 10:27:47+0800").await;
+        assert_eq!(round.promoted, vec![7]);
+        assert!(round.pending.is_empty());
+        assert_eq!(round.expired, vec![8]);
+        assert_eq!(round.kept, vec![8]);
+        assert_eq!(round.new_version.as_deref(), Some("2"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_run_once_relabels_when_bucketing_turned_on() {
+        let storage = Arc::new(MemoryStorage::new());
+        let now = Local::now().format("%Y-%m-%d %H:%M:%S%z").to_string();
+        storage
+            .hset_multiple(
+                "cfg:exp:base",
+                &[
+                    ("version".to_string(), "1".to_string()),
+                    ("start_time".to_string(), now),
+                ],
+            )
+            .await
+            .unwrap();
+        storage
+            .hset_multiple(
+                "cfg:exp:action:targetctr:7",
+                &[
+                    ("a".to_string(), "0.1".to_string()),
+                    ("b".to_string(), "0.2".to_string()),
+                ],
+            )
+            .await
+            .unwrap();
+        let ads_db = AdsDB::new(storage.clone()).await;
+        ads_db.update_adids("1", vec![7]).await;
+        ads_db.set_adid_exp_cfg("1", 7, exp_cfg(7)).await;
+
+        // 版本1运行中开启分桶
+        storage
+            .hset_multiple(
+                "cfg:bucketing",
+                &[
+                    ("exp:1:control".to_string(), "50".to_string()),
+                    ("exp:1:experiment".to_string(), "50".to_string()),
+                ],
+            )
+            .await
+            .unwrap();
+        ads_db.dyn_cfg.sync_key("cfg:bucketing").await;
+
+        let leader = LeaderElector::new(
+            storage.clone(),
+            "test".to_string(),
+            std::time::Duration::from_secs(10),
+        );
+        assert!(leader.tick().await);
+        let lease = leader.lease_value().unwrap();
+        let driver = ExpDriver::new(ads_db.clone(), leader);
+        let round = driver.run_once(&lease).await.unwrap();
+        assert_eq!(round.relabeled, vec![7]);
+        assert_eq!(round.pending, vec![7]);
+
+        let cfg = ads_db.get_adid_exp_cfg("1", 7).await;
+        assert_eq!((cfg.cg_user.as_str(), cfg.eg_user.as_str()), ("cg", "eg"));
+        assert_eq!(cfg.main_action_id, "a");
+        // 分桶后的实验组用户能匹配到广告配置的实验组
+        let bucketing = ads_db.get_bucketing_cfg();
+        let eg_usr = (0..)
+            .map(|i| format!("u{}", i))
+            .find(|usr| crate::service::get_exp_usergroup(&bucketing, "1", usr) == "eg")
+            .unwrap();
+        assert!(cfg.is_exp_group(&crate::service::get_exp_usergroup(&bucketing, "1", &eg_usr)));
+
+        // 已改写的配置不会重复改写
+        let round = driver.run_once(&lease).await.unwrap();
+        assert!(round.relabeled.is_empty());
+    }
+}
@@ -0,0 +1,251 @@
+use std::{
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::dao::{RedisCfgKey_MasterServer, RedisCfgKey_MasterToken, StorageRef};
+
+/// leader状态
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeaderStatus {
+    pub node_id: String,
+    pub is_leader: bool,
+    pub fencing_token: Option<i64>,
+    /// 当前持有租约的节点, 无人持有时为None
+    pub leader: Option<String>,
+    pub leader_token: Option<i64>,
+}
+
+#[derive(Debug, Default)]
+struct LeaseState {
+    /// 租约值 节点id#token
+    value: Option<String>,
+    token: i64,
+    /// 本地认为租约有效的截止时间, 以发起续约的时刻计算
+    deadline: Option<Instant>,
+}
+
+/// 基于redis租约的leader选举: SET NX PX 抢占, 比较后续约,
+/// 每次抢到租约分配一个单调递增的fencing token
+#[derive(Clone)]
+pub struct LeaderElector {
+    storage: StorageRef,
+    node_id: String,
+    lease: Duration,
+    state: Arc<RwLock<LeaseState>>,
+}
+
+impl LeaderElector {
+    pub fn new(storage: StorageRef, node_id: String, lease: Duration) -> Self {
+        Self {
+            storage,
+            node_id,
+            lease,
+            state: Arc::new(RwLock::new(LeaseState::default())),
+        }
+    }
+
+    /// 默认节点id: 主机名-进程号
+    pub fn default_node_id() -> String {
+        let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "localhost".to_string());
+        format!("{}-{}", host, std::process::id())
+    }
+
+    /// 本地判断是否为leader, 租约在本地到期后立即失效
+    pub fn is_leader(&self) -> bool {
+        let state = self.state.read().unwrap();
+        state.value.is_some() && state.deadline.is_some_and(|d| Instant::now() < d)
+    }
+
+    pub fn fencing_token(&self) -> Option<i64> {
+        if self.is_leader() {
+            Some(self.state.read().unwrap().token)
+        } else {
+            None
+        }
+    }
+
+    /// 当前租约值 节点id#token, 作为fencing写入的比较值, 不是leader时为None
+    pub fn lease_value(&self) -> Option<String> {
+        if self.is_leader() {
+            self.state.read().unwrap().value.clone()
+        } else {
+            None
+        }
+    }
+
+    /// 写入前确认lease仍是有效的当前租约: 本地未到期且redis中的持有者未变
+    pub async fn check(&self, lease: &str) -> anyhow::Result<()> {
+        if self.lease_value().as_deref() != Some(lease) {
+            anyhow::bail!("lease {} expired locally", lease);
+        }
+        let holder = self.storage.get(RedisCfgKey_MasterServer).await?;
+        if holder.as_deref() != Some(lease) {
+            anyhow::bail!("lease {} lost, current holder {:?}", lease, holder);
+        }
+        Ok(())
+    }
+
+    /// 续约或抢占租约, 返回本轮之后是否为leader
+    pub async fn tick(&self) -> bool {
+        match self.try_tick().await {
+            Ok(is_leader) => is_leader,
+            Err(e) => {
+                log::error!("leader election error: {}", e);
+                self.is_leader()
+            }
+        }
+    }
+
+    async fn try_tick(&self) -> anyhow::Result<bool> {
+        let started = Instant::now();
+        let ttl_ms = self.lease.as_millis() as usize;
+        let current = self.state.read().unwrap().value.clone();
+
+        if let Some(value) = current {
+            if self
+                .storage
+                .pexpire_if_eq(RedisCfgKey_MasterServer, &value, ttl_ms)
+                .await?
+            {
+                self.state.write().unwrap().deadline = Some(started + self.lease);
+                return Ok(true);
+            }
+            log::warn!("leader lease lost, node={}", self.node_id);
+            *self.state.write().unwrap() = LeaseState::default();
+        }
+
+        // 租约被其他节点持有时不分配token, 避免每个节点每轮都递增token
+        if self.storage.get(RedisCfgKey_MasterServer).await?.is_some() {
+            return Ok(false);
+        }
+        let token = self.storage.incr_by(RedisCfgKey_MasterToken, 1).await?;
+        let value = format!("{}#{}", self.node_id, token);
+        if self
+            .storage
+            .set_nx_px(RedisCfgKey_MasterServer, &value, ttl_ms)
+            .await?
+        {
+            log::info!("became leader, node={} token={}", self.node_id, token);
+            *self.state.write().unwrap() = LeaseState {
+                value: Some(value),
+                token,
+                deadline: Some(started + self.lease),
+            };
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// 主动释放租约, 停机时调用, 其他节点无需等待租约过期即可接管
+    pub async fn resign(&self) {
+        let value = self.state.read().unwrap().value.clone();
+        if let Some(value) = value {
+            if let Err(e) = self
+                .storage
+                .del_if_eq(RedisCfgKey_MasterServer, &value)
+                .await
+            {
+                log::error!("leader resign error: {}", e);
+            }
+        }
+        *self.state.write().unwrap() = LeaseState::default();
+    }
+
+    /// 后台按租约的1/3周期续约
+    pub fn start(&self) {
+        log::info!("starting leader election, node={}", self.node_id);
+        let elector = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(elector.lease / 3);
+            loop {
+                interval.tick().await;
+                elector.tick().await;
+            }
+        });
+    }
+
+    pub async fn status(&self) -> LeaderStatus {
+        let holder = match self.storage.get(RedisCfgKey_MasterServer).await {
+            Ok(holder) => holder,
+            Err(e) => {
+                log::error!("get leader status error: {}", e);
+                None
+            }
+        };
+        let (leader, leader_token) = match holder.as_deref().and_then(|h| h.rsplit_once('#')) {
+            Some((node, token)) => (Some(node.to_string()), token.parse().ok()),
+            None => (holder, None),
+        };
+
+        LeaderStatus {
+            node_id: self.node_id.clone(),
+            is_leader: self.is_leader(),
+            fencing_token: self.fencing_token(),
+            leader,
+            leader_token,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dao::MemoryStorage;
+
+    #[tokio::test]
+    async fn test_leader_election() {
+        let storage: StorageRef = Arc::new(MemoryStorage::new());
+        let a = LeaderElector::new(storage.clone(), "a".to_string(), Duration::from_secs(10));
+        let b = LeaderElector::new(storage.clone(), "b".to_string(), Duration::from_secs(10));
+
+        assert!(a.tick().await);
+        assert!(!b.tick().await);
+        assert!(!b.tick().await);
+        assert!(a.tick().await);
+        assert_eq!(a.fencing_token(), Some(1));
+        assert_eq!(a.lease_value().as_deref(), Some("a#1"));
+        assert!(a.check("a#1").await.is_ok());
+        assert!(b.check("a#1").await.is_err());
+        // 非leader的轮询不递增token
+        assert_eq!(
+            storage
+                .get(RedisCfgKey_MasterToken)
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("1")
+        );
+        assert_eq!(b.fencing_token(), None);
+
+        let status = b.status().await;
+        assert_eq!(status.leader.as_deref(), Some("a"));
+        assert_eq!(status.leader_token, Some(1));
+        assert!(!status.is_leader);
+
+        a.resign().await;
+        assert!(!a.is_leader());
+        assert!(b.tick().await);
+        // token单调递增, 旧leader的写入可以被拒绝
+        assert_eq!(b.fencing_token(), Some(2));
+        assert!(a.check("a#1").await.is_err());
+        assert!(!a.tick().await);
+    }
+
+    #[tokio::test]
+    async fn test_leader_lease_expire() {
+        let storage: StorageRef = Arc::new(MemoryStorage::new());
+        let a = LeaderElector::new(storage.clone(), "a".to_string(), Duration::from_millis(20));
+        let b = LeaderElector::new(storage.clone(), "b".to_string(), Duration::from_secs(10));
+
+        assert!(a.tick().await);
+        let lease = a.lease_value().unwrap();
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(!a.is_leader());
+        assert!(a.check(&lease).await.is_err());
+        assert!(b.tick().await);
+        assert!(!a.tick().await);
+    }
+}
@@ -1,16 +1,30 @@
-use crate::model::RangeValue;
-
+pub mod analysis;
+pub mod event;
 pub mod exp_driver;
+pub mod leader;
 pub mod prodiction;
+pub mod shadow;
+pub mod strategy;
 
+pub use analysis::*;
+pub use event::*;
+pub use exp_driver::*;
+pub use leader::*;
 pub use prodiction::*;
+pub use shadow::*;
 
-pub fn find_target_val(cfgs: &Vec<RangeValue>, target: f64) -> f64 {
-    for cfg in cfgs {
-        if cfg.min >= target && target < cfg.max {
-            return cfg.value;
-        }
-    }
+use crate::model::BucketingCfg;
 
-    return 0.0;
+/// 用户分组: md5(usr) 的最后一位16进制字符
+pub fn get_usergroup(usr: &str) -> String {
+    let usr_md5 = format!("{:x}", md5::compute(usr));
+    usr_md5[usr_md5.len() - 1..].to_string()
+}
+
+/// 实验版本中的用户分组: 版本配置了分桶时为 cg/eg/holdout/main, 否则同 `get_usergroup`
+pub fn get_exp_usergroup(bucketing: &BucketingCfg, version: &str, usr: &str) -> String {
+    match bucketing.usergroup(version, usr) {
+        Some(group) => group.to_string(),
+        None => get_usergroup(usr),
+    }
 }
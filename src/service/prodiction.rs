@@ -1,94 +1,181 @@
 // #![allow(dead_code)]
 // #![allow(unused_variables)]
 
-use rand::prelude::*;
+use std::sync::Arc;
 
+use super::strategy::*;
 use crate::dao::*;
 use crate::model::*;
-use md5;
 
 #[derive(Clone)]
 pub struct ProdictionService {
     ads_dao: AdsDB,
+    strategies: Arc<StrategyRegistry>,
 }
 
 impl ProdictionService {
     pub fn new(ads_dao: AdsDB) -> Self {
-        Self { ads_dao }
+        let strategies = StrategyRegistry::with_builtin(&ads_dao);
+        Self::with_strategies(ads_dao, strategies)
     }
 
-    pub fn predict(&self, request: &Request) -> Response {
-        let usrhash = md5::compute(&request.usr);
-        let usr_md5 = format!("{:x}", usrhash);
-        let usergroup = &usr_md5[usr_md5.len() - 1..];
+    pub fn with_strategies(ads_dao: AdsDB, strategies: StrategyRegistry) -> Self {
+        Self {
+            ads_dao,
+            strategies: Arc::new(strategies),
+        }
+    }
+
+    /// 请求指定的策略 > service_type的默认策略 > rule
+    pub fn select_strategy(&self, request: &Request) -> StrategyRef {
+        if let Some(name) = request.model.as_deref().filter(|name| !name.is_empty()) {
+            match self.strategies.get(name) {
+                Some(strategy) => return strategy,
+                None => {
+                    log::warn!(
+                        "unknown model {}, use default strategy, registered: {:?}",
+                        name,
+                        self.strategies.names()
+                    );
+                    metrics::increment_counter!("predict_unknown_model_total");
+                }
+            }
+        }
+
+        self.ads_dao
+            .get_default_strategy(request.service_type)
+            .and_then(|name| {
+                let strategy = self.strategies.get(&name);
+                if strategy.is_none() {
+                    log::warn!(
+                        "unknown default strategy {}, registered: {:?}",
+                        name,
+                        self.strategies.names()
+                    );
+                }
+                strategy
+            })
+            .or_else(|| self.strategies.get(DEFAULT_STRATEGY))
+            .expect("default strategy must be registered")
+    }
+
+    /// 影子策略, 跳过线上策略和未注册的策略
+    fn shadow_strategies(&self, cfg: &ShadowCfg, live: &str) -> Vec<StrategyRef> {
+        cfg.strategies
+            .iter()
+            .filter(|name| name.as_str() != live)
+            .filter_map(|name| {
+                let strategy = self.strategies.get(name);
+                if strategy.is_none() {
+                    log::warn!("unknown shadow strategy {}", name);
+                }
+                strategy
+            })
+            .collect()
+    }
+
+    /// 按采样率在后台执行影子策略, 只记录日志和指标
+    fn spawn_shadow(
+        &self,
+        ctx: &PredictContext<'_>,
+        signals: &[AdSignals],
+        live: &str,
+        decisions: &[Decision],
+    ) {
+        let cfg = self.ads_dao.get_shadow_cfg();
+        if !super::sample_shadow(&cfg) {
+            return;
+        }
+        let shadows = self.shadow_strategies(&cfg, live);
+        if shadows.is_empty() {
+            return;
+        }
+        let run = super::ShadowRun::capture(ctx, signals, live, decisions, shadows);
+        tokio::spawn(run.run());
+    }
+
+    pub async fn predict(&self, request: &Request) -> Response {
         let exp_base_cfg: ExpBaseCfg = self.ads_dao.get_exp_base_cfg();
+        let usergroup = super::get_exp_usergroup(
+            &self.ads_dao.get_bucketing_cfg(),
+            &exp_base_cfg.version,
+            &request.usr,
+        );
+        let usergroup = usergroup.as_str();
         let ab_params: AbParams = self.ads_dao.get_exp_ab_params();
-        self.ads_dao
+        let predict_cfg = self.ads_dao.get_predict_cfg();
+        let ctr_control = self.ads_dao.get_ctr_control_cfg();
+        let new_ad_ids = self
+            .ads_dao
             .add_adids_to_localcache(&exp_base_cfg.version, &request.ad_id);
-        let user_daily_total_tempt_click = self.ads_dao.query_temp_click(&request.usr);
+        if !new_ad_ids.is_empty() {
+            self.ads_dao
+                .update_adids(&exp_base_cfg.version, new_ad_ids)
+                .await;
+        }
+        let user_daily_total_tempt_click = self.ads_dao.query_temp_click(&request.usr).await;
 
         //let adid_whitelist: HashSet<u64> = self.ads_dao.get_adid_whitelist();
-        let tempt_click_cfg: Vec<RangeValue> = self.ads_dao.get_signal_daily_total_tempt_click();
-        let adid_fill_rate_cfg: Vec<RangeValue> = self.ads_dao.get_signal_ad_id_fill_rate();
-        let adid_show_rate_cfg: Vec<RangeValue> = self.ads_dao.get_signal_ad_id_show_rate();
-        let adid_click_rate_cfg: Vec<RangeValue> = self.ads_dao.get_signal_ad_id_click_rate();
-
-        let rate_a = super::find_target_val(&tempt_click_cfg, user_daily_total_tempt_click as f64);
-
-        let date = chrono::Local::now().to_rfc2822();
-
-        let mut predictions = Vec::new();
-        for adid in request.ad_id.iter() {
-            let ad_id_realtime_event: AdEvent = self
-                .ads_dao
-                .get_realtime_ad_id_window_events(usergroup, *adid);
-            let user_daily_ad_id_event: AdEvent =
-                self.ads_dao
-                    .get_user_daily_ad_id_event(*adid, &request.usr, &date);
-
-            let fill_rate = user_daily_ad_id_event.get_fill_rate(&ab_params);
-            let show_rate = user_daily_ad_id_event.get_show_rate(&ab_params);
-            let click_rate = user_daily_ad_id_event.get_click_rate(&ab_params);
-            let rate_b = super::find_target_val(&adid_fill_rate_cfg, fill_rate);
-            let rate_c = super::find_target_val(&adid_show_rate_cfg, show_rate);
-            let rate_d = super::find_target_val(&adid_click_rate_cfg, click_rate);
-            let window_ctr = ad_id_realtime_event.get_click_rate_without_ab();
-
-            let ad_exp_cfg = self.ads_dao.get_adid_exp_cfg(&exp_base_cfg.version, *adid);
-
-            // 目标CTR
-            let target_ctr = if ad_exp_cfg.is_exp_group(usergroup) {
-                ad_exp_cfg.exp_action_value // 如果是试验组
-            } else {
-                ad_exp_cfg.main_action_value // 对照与主版本
-            };
-
-            let mut total_rate = rate_a * rate_b * rate_c * rate_d;
-            // 区间判断 [-N,-30, 30,+N]
-            if window_ctr < target_ctr {
-                if (window_ctr + target_ctr * 0.3) < target_ctr {
-                    // 策略A
-                    total_rate = total_rate * 2 as f64
-                }
-            } else {
-                if (target_ctr + target_ctr * 0.3) < window_ctr {
-                    // 策略C
-                    total_rate = total_rate * 0.5
-                }
-            }
+        let ctx = PredictContext {
+            request,
+            usergroup,
+            exp_base_cfg: &exp_base_cfg,
+            ab_params: &ab_params,
+            ctr_control: &ctr_control,
+            tempt_click: user_daily_total_tempt_click,
+            tempt_click_table: self.ads_dao.get_signal_daily_total_tempt_click(),
+            fill_rate_table: self.ads_dao.get_signal_ad_id_fill_rate(),
+            show_rate_table: self.ads_dao.get_signal_ad_id_show_rate(),
+            click_rate_table: self.ads_dao.get_signal_ad_id_click_rate(),
+            debug: request.is_debug.unwrap_or(false),
+            draw_mode: predict_cfg.draw,
+            time_bucket: time_bucket(
+                chrono::Local::now().timestamp(),
+                predict_cfg.draw_bucket_secs,
+            ),
+            draws: Default::default(),
+        };
+
+        let ad_signals = self
+            .ads_dao
+            .load_ad_signals(
+                usergroup,
+                &request.usr,
+                &exp_base_cfg.version,
+                &request.ad_id,
+            )
+            .await;
 
-            // 随机预估
-            let prediction = if total_rate >= exp_base_cfg.base_value {
-                let mut rng = rand::thread_rng();
-                let probability: f64 = rng.gen();
-                let state = if total_rate >= probability { 1 } else { 0 };
-                state
-            } else {
-                0
-            };
-
-            predictions.push(AdItem::new(*adid, prediction));
+        let strategy = self.select_strategy(request);
+        metrics::increment_counter!("predict_strategy_total", "strategy" => strategy.name().to_string());
+        let mut decisions = strategy.decide(&ctx, &ad_signals).await;
+        let with_scores = request.with_scores.unwrap_or(false);
+        let ranks = if with_scores || request.top_k.is_some() {
+            rank(&decisions)
+        } else {
+            vec![]
+        };
+        if let Some(k) = request.top_k {
+            select_top_k(&mut decisions, &ranks, k);
         }
+        self.spawn_shadow(&ctx, &ad_signals, strategy.name(), &decisions);
+        let predictions = decisions
+            .into_iter()
+            .enumerate()
+            .map(|(i, decision)| {
+                let mut item = AdItem {
+                    explain: decision.explain,
+                    ..AdItem::new(decision.ad_id, decision.value)
+                };
+                if with_scores {
+                    item.score = Some(decision.score);
+                    item.probability = decision.probability;
+                    item.rank = Some(ranks[i]);
+                }
+                item.action_id = decision.action_id;
+                item
+            })
+            .collect();
 
         Response {
             code: 0,
@@ -100,8 +187,281 @@ impl ProdictionService {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
     use super::*;
 
+    async fn new_service(base: &str) -> ProdictionService {
+        let storage = Arc::new(MemoryStorage::new());
+        let hash = |kvs: &[(&str, &str)]| -> Vec<(String, String)> {
+            kvs.iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        storage
+            .hset_multiple(
+                "cfg:exp:base",
+                &hash(&[
+                    ("version", "1"),
+                    ("base", base),
+                    ("start_time", "2022-05-30 10:27:47+0800"),
+                ]),
+            )
+            .await
+            .unwrap();
+        for key in [
+            "cfg:signal:tempclick",
+            "cfg:signal:adid:fillrate",
+            "cfg:signal:adid:showrate",
+            "cfg:signal:adid:clickrate",
+        ] {
+            storage
+                .hset_multiple(key, &hash(&[("0_1", "1")]))
+                .await
+                .unwrap();
+        }
+        ProdictionService::new(AdsDB::new(storage).await)
+    }
+
+    fn new_request() -> Request {
+        Request {
+            usr: "u1".to_string(),
+            ad_id: vec![1, 2, 3],
+            service_type: 1,
+            model: None,
+            is_debug: None,
+            request_id: None,
+            with_scores: None,
+            top_k: None,
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_predict_end_to_end() {
+        let service = new_service("0").await;
+        let response = service.predict(&new_request()).await;
+        assert_eq!(response.code, 0);
+        assert_eq!(response.items.len(), 3);
+        assert!(response.items.iter().all(|item| item.value == 1));
+
+        let service = new_service("2").await;
+        let response = service.predict(&new_request()).await;
+        assert!(response.items.iter().all(|item| item.value == 0));
+    }
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_select_strategy() {
+        let service = new_service("0").await;
+        let mut request = new_request();
+        assert_eq!(service.select_strategy(&request).name(), "rule");
+
+        request.model = Some("never".to_string());
+        let response = service.predict(&request).await;
+        assert!(response.items.iter().all(|item| item.value == 0));
+
+        // 未知策略回退到默认策略
+        request.model = Some("no-such-model".to_string());
+        assert_eq!(service.select_strategy(&request).name(), "rule");
+
+        service
+            .ads_dao
+            .redis_dao
+            .storage
+            .hset_multiple(
+                "cfg:strategy:default",
+                &[
+                    ("1".to_string(), "threshold".to_string()),
+                    ("default".to_string(), "never".to_string()),
+                ],
+            )
+            .await
+            .unwrap();
+        service
+            .ads_dao
+            .dyn_cfg
+            .sync_key("cfg:strategy:default")
+            .await;
+        request.model = None;
+        assert_eq!(service.select_strategy(&request).name(), "threshold");
+        request.service_type = 2;
+        assert_eq!(service.select_strategy(&request).name(), "never");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_predict_debug_explain() {
+        let service = new_service("0").await;
+        let response = service.predict(&new_request()).await;
+        assert!(response.items.iter().all(|item| item.explain.is_none()));
+
+        let mut request = new_request();
+        request.is_debug = Some(true);
+        let response = service.predict(&request).await;
+        let explain = response.items[0].explain.as_ref().unwrap();
+        assert_eq!(explain.strategy, "rule");
+        assert_eq!(explain.usergroup, crate::service::get_usergroup("u1"));
+        assert_eq!(explain.rate_a, 1.0);
+        assert_eq!(
+            explain.bucket_b,
+            Some(RangeValue {
+                min: 0.0,
+                max: 1.0,
+                value: 1.0
+            })
+        );
+        assert_eq!(explain.branch, CtrBranch::None);
+        assert_eq!(explain.total_rate, 1.0);
+        assert!(explain.draw.is_some());
+
+        let json = serde_json::to_value(&response.items[0]).unwrap();
+        assert_eq!(json["explain"]["branch"], "none");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_predict_scores_and_top_k() {
+        let service = new_service("0").await;
+        let mut request = new_request();
+        let response = service.predict(&request).await;
+        assert!(response.items.iter().all(|item| item.score.is_none()));
+
+        request.with_scores = Some(true);
+        let response = service.predict(&request).await;
+        let ranks: Vec<Option<usize>> = response.items.iter().map(|item| item.rank).collect();
+        assert_eq!(ranks, vec![Some(1), Some(2), Some(3)]);
+        assert!(response.items.iter().all(|item| item.score == Some(1.0)));
+        assert!(response.items.iter().all(|item| item.probability.is_some()));
+
+        request.top_k = Some(2);
+        let response = service.predict(&request).await;
+        let values: Vec<u8> = response.items.iter().map(|item| item.value).collect();
+        assert_eq!(values, vec![1, 1, 0]);
+        assert!(response.items.iter().all(|item| item.probability.is_none()));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_predict_bucketed_usergroup() {
+        let service = new_service("0").await;
+        let mut request = new_request();
+        request.is_debug = Some(true);
+        let response = service.predict(&request).await;
+        let explain = response.items[0].explain.as_ref().unwrap();
+        assert_eq!(explain.usergroup, crate::service::get_usergroup("u1"));
+
+        service
+            .ads_dao
+            .redis_dao
+            .storage
+            .hset_multiple(
+                "cfg:bucketing",
+                &[
+                    ("exp:1:control".to_string(), "50".to_string()),
+                    ("exp:1:experiment".to_string(), "50".to_string()),
+                ],
+            )
+            .await
+            .unwrap();
+        service.ads_dao.dyn_cfg.sync_key("cfg:bucketing").await;
+        let response = service.predict(&request).await;
+        let explain = response.items[0].explain.as_ref().unwrap();
+        assert!(["cg", "eg"].contains(&explain.usergroup.as_str()));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_predict_bandit() {
+        let service = new_service("0").await;
+        service
+            .ads_dao
+            .redis_dao
+            .storage
+            .hset_multiple(
+                "cfg:exp:action:targetctr:1",
+                &[
+                    ("x".to_string(), "0.1".to_string()),
+                    ("y".to_string(), "0.2".to_string()),
+                ],
+            )
+            .await
+            .unwrap();
+
+        for model in ["thompson", "ucb1", "epsilon_greedy"] {
+            let mut request = new_request();
+            request.model = Some(model.to_string());
+            request.is_debug = Some(true);
+            let response = service.predict(&request).await;
+            let item = &response.items[0];
+            let action_id = item.action_id.as_deref().unwrap();
+            assert!(["x", "y"].contains(&action_id));
+            let explain = item.explain.as_ref().unwrap();
+            assert_eq!(explain.strategy, model);
+            assert_eq!(explain.target_ctr, if action_id == "x" { 0.1 } else { 0.2 });
+            // 没有可选动作的广告与规则策略一致
+            assert!(response.items[1].action_id.is_none());
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_predict_shadow_does_not_affect_response() {
+        let service = new_service("0").await;
+        service
+            .ads_dao
+            .redis_dao
+            .storage
+            .hset_multiple(
+                "cfg:shadow",
+                &[
+                    ("rate".to_string(), "1".to_string()),
+                    (
+                        "strategies".to_string(),
+                        "never,rule,no-such-model".to_string(),
+                    ),
+                ],
+            )
+            .await
+            .unwrap();
+        service.ads_dao.dyn_cfg.sync_key("cfg:shadow").await;
+
+        let request = new_request();
+        let cfg = service.ads_dao.get_shadow_cfg();
+        let shadows: Vec<String> = service
+            .shadow_strategies(&cfg, "rule")
+            .iter()
+            .map(|s| s.name().to_string())
+            .collect();
+        assert_eq!(shadows, vec!["never"]);
+
+        let response = service.predict(&request).await;
+        assert!(response.items.iter().all(|item| item.value == 1));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_predict_seeded_draw() {
+        let service = new_service("0").await;
+        service
+            .ads_dao
+            .redis_dao
+            .storage
+            .hset_multiple("cfg:predict", &[("draw".to_string(), "seeded".to_string())])
+            .await
+            .unwrap();
+        service.ads_dao.dyn_cfg.sync_key("cfg:predict").await;
+
+        let mut request = new_request();
+        request.is_debug = Some(true);
+        request.request_id = Some("r1".to_string());
+        let draws = |response: Response| -> Vec<Option<f64>> {
+            response
+                .items
+                .into_iter()
+                .map(|item| item.explain.unwrap().draw)
+                .collect()
+        };
+        let first = draws(service.predict(&request).await);
+        assert!(first.iter().all(|draw| draw.is_some()));
+        // 同一时间桶内可重放
+        assert_eq!(first, draws(service.predict(&request).await));
+
+        request.request_id = Some("r2".to_string());
+        assert_ne!(first, draws(service.predict(&request).await));
+    }
+
     #[test]
     fn test_md5() {
         let usrhash = md5::compute("1234");
@@ -109,5 +469,6 @@ mod tests {
         let usergroup = &usr_md5[usr_md5.len() - 1..];
         println!("{}", usr_md5);
         println!("{}", usergroup);
+        assert_eq!(usergroup, crate::service::get_usergroup("1234"));
     }
 }
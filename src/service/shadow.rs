@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use rand::Rng;
+
+use super::strategy::*;
+use crate::model::*;
+
+/// 本次请求是否执行影子策略
+pub fn sample_shadow(cfg: &ShadowCfg) -> bool {
+    !cfg.strategies.is_empty() && cfg.rate > 0.0 && rand::thread_rng().gen::<f64>() < cfg.rate
+}
+
+/// 影子策略与线上策略对比结果
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShadowResult {
+    pub strategy: String,
+    pub agree: usize,
+    pub disagree: usize,
+    /// 不一致的广告: (ad_id, 线上value, 影子value)
+    pub diffs: Vec<(i64, u8, u8)>,
+}
+
+/// 按广告对比两组决策, 决策与signals一一对应
+pub fn compare(strategy: &str, live: &[Decision], shadow: &[Decision]) -> ShadowResult {
+    let diffs: Vec<(i64, u8, u8)> = live
+        .iter()
+        .zip(shadow)
+        .filter(|(l, s)| l.value != s.value)
+        .map(|(l, s)| (l.ad_id, l.value, s.value))
+        .collect();
+    ShadowResult {
+        strategy: strategy.to_string(),
+        agree: live.len().min(shadow.len()) - diffs.len(),
+        disagree: diffs.len(),
+        diffs,
+    }
+}
+
+/// 一次影子执行的输入, 从线上请求复制, 在后台执行不影响响应
+pub struct ShadowRun {
+    live: String,
+    live_decisions: Vec<Decision>,
+    shadows: Vec<StrategyRef>,
+    request: Request,
+    usergroup: String,
+    exp_base_cfg: ExpBaseCfg,
+    ab_params: AbParams,
+    ctr_control: CtrControlCfg,
+    tempt_click: f64,
+    tables: [Arc<RangeTable>; 4],
+    draw_mode: DrawMode,
+    time_bucket: i64,
+    /// 线上策略的抽样, 影子策略使用同样的随机数, 对比结果只反映策略本身的差异
+    draws: HashMap<i64, f64>,
+    signals: Vec<AdSignals>,
+}
+
+impl ShadowRun {
+    pub fn capture(
+        ctx: &PredictContext<'_>,
+        signals: &[AdSignals],
+        live: &str,
+        live_decisions: &[Decision],
+        shadows: Vec<StrategyRef>,
+    ) -> Self {
+        Self {
+            live: live.to_string(),
+            live_decisions: live_decisions.to_vec(),
+            shadows,
+            request: ctx.request.clone(),
+            usergroup: ctx.usergroup.to_string(),
+            exp_base_cfg: ctx.exp_base_cfg.clone(),
+            ab_params: ctx.ab_params.clone(),
+            ctr_control: ctx.ctr_control.clone(),
+            tempt_click: ctx.tempt_click,
+            tables: [
+                ctx.tempt_click_table.clone(),
+                ctx.fill_rate_table.clone(),
+                ctx.show_rate_table.clone(),
+                ctx.click_rate_table.clone(),
+            ],
+            draw_mode: ctx.draw_mode,
+            time_bucket: ctx.time_bucket,
+            draws: ctx.draws.lock().unwrap().clone(),
+            signals: signals.to_vec(),
+        }
+    }
+
+    /// 执行所有影子策略, 记录日志和一致/不一致指标
+    pub async fn run(self) -> Vec<ShadowResult> {
+        let [tempt_click_table, fill_rate_table, show_rate_table, click_rate_table] =
+            self.tables.clone();
+        let ctx = PredictContext {
+            request: &self.request,
+            usergroup: &self.usergroup,
+            exp_base_cfg: &self.exp_base_cfg,
+            ab_params: &self.ab_params,
+            ctr_control: &self.ctr_control,
+            tempt_click: self.tempt_click,
+            tempt_click_table,
+            fill_rate_table,
+            show_rate_table,
+            click_rate_table,
+            debug: false,
+            draw_mode: self.draw_mode,
+            time_bucket: self.time_bucket,
+            draws: Mutex::new(self.draws.clone()),
+        };
+
+        let mut results = Vec::with_capacity(self.shadows.len());
+        for shadow in &self.shadows {
+            let mut decisions = shadow.decide(&ctx, &self.signals).await;
+            // 与线上一样按top-K调整, 对比的是实际返回的结果
+            if let Some(k) = self.request.top_k {
+                let ranks = rank(&decisions);
+                select_top_k(&mut decisions, &ranks, k);
+            }
+            let result = compare(shadow.name(), &self.live_decisions, &decisions);
+            let (live, name) = (&self.live, &result.strategy);
+            metrics::increment_counter!("predict_shadow_total", "live" => live.clone(), "shadow" => name.clone());
+            metrics::counter!("predict_shadow_agree_total", result.agree as u64, "live" => live.clone(), "shadow" => name.clone());
+            metrics::counter!("predict_shadow_disagree_total", result.disagree as u64, "live" => live.clone(), "shadow" => name.clone());
+            log::info!(
+                "shadow {} vs {}: usr={} request_id={:?} agree={} disagree={} diffs={:?}",
+                result.strategy,
+                self.live,
+                self.request.usr,
+                self.request.request_id,
+                result.agree,
+                result.disagree,
+                result.diffs
+            );
+            results.push(result);
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    fn decisions(values: &[u8]) -> Vec<Decision> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, &value)| Decision {
+                ad_id: i as i64 + 1,
+                value,
+                score: value as f64,
+                probability: None,
+                action_id: None,
+                explain: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_compare() {
+        let result = compare("never", &decisions(&[1, 0, 1]), &decisions(&[0, 0, 0]));
+        assert_eq!(result.agree, 1);
+        assert_eq!(result.disagree, 2);
+        assert_eq!(result.diffs, vec![(1, 1, 0), (3, 1, 0)]);
+    }
+
+    #[tokio::test]
+    async fn test_identical_shadow_agrees() {
+        let (half, _) = RangeTable::parse(&BTreeMap::from([(
+            "-inf_inf".to_string(),
+            "0.5".to_string(),
+        )]));
+        let half = Arc::new(half);
+        let (one, _) =
+            RangeTable::parse(&BTreeMap::from([("-inf_inf".to_string(), "1".to_string())]));
+        let one = Arc::new(one);
+        let (exp_base_cfg, ab_params, ctr_control) = Default::default();
+
+        for top_k in [None, Some(50)] {
+            let request = Request {
+                usr: "u1".to_string(),
+                ad_id: (1..=200).collect(),
+                service_type: 1,
+                model: None,
+                is_debug: None,
+                request_id: None,
+                with_scores: None,
+                top_k,
+            };
+            let signals: Vec<AdSignals> = request
+                .ad_id
+                .iter()
+                .map(|&ad_id| AdSignals {
+                    ad_id,
+                    ..Default::default()
+                })
+                .collect();
+            let ctx = PredictContext {
+                request: &request,
+                usergroup: "a",
+                exp_base_cfg: &exp_base_cfg,
+                ab_params: &ab_params,
+                ctr_control: &ctr_control,
+                tempt_click: 0.0,
+                tempt_click_table: half.clone(),
+                fill_rate_table: one.clone(),
+                show_rate_table: one.clone(),
+                click_rate_table: one.clone(),
+                debug: false,
+                draw_mode: DrawMode::Random,
+                time_bucket: 0,
+                draws: Default::default(),
+            };
+
+            let mut live = RuleStrategy::new().decide(&ctx, &signals).await;
+            // total_rate为0.5, 抽样结果有投放也有不投放
+            assert!(live.iter().any(|d| d.value == 1) && live.iter().any(|d| d.value == 0));
+            if let Some(k) = top_k {
+                let ranks = rank(&live);
+                // top-K之外有抽中投放的广告被去掉
+                assert!(live.iter().zip(&ranks).any(|(d, &r)| d.value == 1 && r > k));
+                select_top_k(&mut live, &ranks, k);
+                assert_eq!(live.iter().filter(|d| d.value == 1).count(), k);
+            }
+
+            let shadow: StrategyRef = Arc::new(RuleStrategy::new());
+            let results = ShadowRun::capture(&ctx, &signals, "rule", &live, vec![shadow])
+                .run()
+                .await;
+            assert_eq!(results[0].agree, 200);
+            assert_eq!(results[0].disagree, 0);
+        }
+    }
+
+    #[test]
+    fn test_sample_shadow() {
+        let mut cfg = ShadowCfg {
+            rate: 1.0,
+            strategies: vec![],
+        };
+        assert!(!sample_shadow(&cfg));
+        cfg.strategies = vec!["never".to_string()];
+        assert!(sample_shadow(&cfg));
+        cfg.rate = 0.0;
+        assert!(!sample_shadow(&cfg));
+    }
+}
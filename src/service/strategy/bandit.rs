@@ -0,0 +1,384 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use rand::Rng;
+use rand_distr::{Beta, Distribution};
+
+use super::{Decision, PredictContext, RuleStrategy, Strategy};
+use crate::dao::AdsDB;
+use crate::model::*;
+
+/// 动作选择方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BanditPolicy {
+    /// 对各动作点击率的Beta后验采样, 取采样值最大的动作
+    Thompson,
+    /// 平滑点击率 + c * sqrt(ln(总展示) / 动作展示), 没有展示的动作优先
+    Ucb1,
+    /// 以epsilon的概率随机选择, 否则选平滑点击率最高的动作
+    EpsilonGreedy,
+}
+
+/// bandit策略: 每个广告的各目标CTR动作以展示/点击计数为奖励统计, 每次请求选择一个动作,
+/// 以该动作的目标CTR按规则策略决策
+pub struct BanditStrategy {
+    name: &'static str,
+    policy: BanditPolicy,
+    ads_dao: AdsDB,
+    rule: RuleStrategy,
+    /// 最近一次告警的不合法先验 (click_a, click_b), 同样的配置只告警一次
+    invalid_prior: Mutex<Option<(f64, f64)>>,
+}
+
+impl BanditStrategy {
+    pub fn new(name: &'static str, policy: BanditPolicy, ads_dao: AdsDB) -> Self {
+        Self {
+            name,
+            policy,
+            ads_dao,
+            rule: RuleStrategy::new(),
+            invalid_prior: Mutex::new(None),
+        }
+    }
+
+    pub fn thompson(ads_dao: AdsDB) -> Self {
+        Self::new("thompson", BanditPolicy::Thompson, ads_dao)
+    }
+
+    pub fn ucb1(ads_dao: AdsDB) -> Self {
+        Self::new("ucb1", BanditPolicy::Ucb1, ads_dao)
+    }
+
+    pub fn epsilon_greedy(ads_dao: AdsDB) -> Self {
+        Self::new("epsilon_greedy", BanditPolicy::EpsilonGreedy, ads_dao)
+    }
+
+    /// Thompson采样的先验, 不合法时使用 Beta(1, 1), 记录指标并告警
+    fn prior(&self, ab_params: &AbParams) -> (f64, f64) {
+        if let Some(prior) = beta_prior(ab_params) {
+            return prior;
+        }
+        metrics::increment_counter!("bandit_invalid_prior_total", "strategy" => self.name);
+        let params = (ab_params.click_a, ab_params.click_b);
+        let mut warned = self.invalid_prior.lock().unwrap();
+        if *warned != Some(params) {
+            log::warn!(
+                "{}: invalid beta prior click_a={} click_b={}, fall back to Beta(1, 1)",
+                self.name,
+                params.0,
+                params.1
+            );
+            *warned = Some(params);
+        }
+        DEFAULT_PRIOR
+    }
+
+    /// 选中的动作下标及其得分
+    fn choose(
+        &self,
+        events: &[AdEvent],
+        ab_params: &AbParams,
+        prior: (f64, f64),
+        cfg: &BanditCfg,
+    ) -> Option<(usize, f64)> {
+        let mut rng = rand::thread_rng();
+        match self.policy {
+            BanditPolicy::Thompson => {
+                let posteriors: Vec<(f64, f64)> = events
+                    .iter()
+                    .map(|event| beta_posterior(event, prior))
+                    .collect();
+                sample_arm(&mut rng, &posteriors)
+            }
+            BanditPolicy::Ucb1 => ucb1_arm(events, ab_params, cfg.ucb_c),
+            BanditPolicy::EpsilonGreedy => {
+                epsilon_greedy_arm(&mut rng, events, ab_params, cfg.epsilon)
+            }
+        }
+    }
+}
+
+/// 广告可选的动作及目标CTR, 没有配置动作时使用实验配置中的主动作和实验动作
+pub fn arms(signal: &AdSignals, actions: BTreeMap<String, f64>) -> Vec<(String, f64)> {
+    if !actions.is_empty() {
+        return actions.into_iter().collect();
+    }
+    let cfg = &signal.exp_cfg;
+    if cfg.is_empty() {
+        return vec![];
+    }
+    let mut arms = vec![(cfg.main_action_id.clone(), cfg.main_action_value)];
+    if cfg.eg_action_id != cfg.main_action_id {
+        arms.push((cfg.eg_action_id.clone(), cfg.exp_action_value));
+    }
+    arms
+}
+
+/// 先验参数不合法时使用的先验
+pub const DEFAULT_PRIOR: (f64, f64) = (1.0, 1.0);
+
+/// 点击率的Beta先验: Beta(click_a, click_b + 1 - click_a), 使后验均值与 `AdEvent::get_click_rate` 一致,
+/// 参数不合法 (如全为0、click_a > click_b + 1) 时返回None
+pub fn beta_prior(ab_params: &AbParams) -> Option<(f64, f64)> {
+    let alpha = ab_params.click_a;
+    let beta = ab_params.click_b + 1.0 - ab_params.click_a;
+    (alpha > 0.0 && beta > 0.0 && alpha.is_finite() && beta.is_finite()).then_some((alpha, beta))
+}
+
+/// 点击率的Beta后验, 均值为 (click + click_a) / (show + 1 + click_b)
+pub fn beta_posterior(event: &AdEvent, (alpha, beta): (f64, f64)) -> (f64, f64) {
+    let click = event.click.max(0) as f64;
+    let miss = (event.show - event.click).max(0) as f64;
+    (alpha + click, beta + miss)
+}
+
+/// 对每个后验采样, 返回采样值最大的下标及采样值
+pub fn sample_arm<R: Rng>(rng: &mut R, posteriors: &[(f64, f64)]) -> Option<(usize, f64)> {
+    posteriors
+        .iter()
+        .enumerate()
+        .filter_map(|(i, &(alpha, beta))| Beta::new(alpha, beta).ok().map(|d| (i, d.sample(rng))))
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+/// UCB1: 没有展示的动作得分为无穷大, 按顺序优先选择
+pub fn ucb1_arm(events: &[AdEvent], ab_params: &AbParams, c: f64) -> Option<(usize, f64)> {
+    let total: i64 = events.iter().map(|e| e.show.max(0)).sum();
+    let ln_total = (total.max(1) as f64).ln();
+    events
+        .iter()
+        .map(|event| {
+            if event.show <= 0 {
+                f64::INFINITY
+            } else {
+                event.get_click_rate(ab_params) + c * (ln_total / event.show as f64).sqrt()
+            }
+        })
+        .enumerate()
+        // 得分相同时取靠前的动作
+        .fold(None, |best: Option<(usize, f64)>, (i, score)| match best {
+            Some((_, s)) if s >= score => best,
+            _ => Some((i, score)),
+        })
+}
+
+/// epsilon-greedy: 得分为动作的平滑点击率
+pub fn epsilon_greedy_arm<R: Rng>(
+    rng: &mut R,
+    events: &[AdEvent],
+    ab_params: &AbParams,
+    epsilon: f64,
+) -> Option<(usize, f64)> {
+    if events.is_empty() {
+        return None;
+    }
+    let i = if rng.gen::<f64>() < epsilon {
+        rng.gen_range(0..events.len())
+    } else {
+        events
+            .iter()
+            .enumerate()
+            .max_by(|a, b| {
+                a.1.get_click_rate(ab_params)
+                    .total_cmp(&b.1.get_click_rate(ab_params))
+                    .then(b.0.cmp(&a.0))
+            })
+            .map(|(i, _)| i)?
+    };
+    Some((i, events[i].get_click_rate(ab_params)))
+}
+
+#[async_trait]
+impl Strategy for BanditStrategy {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    /// 所有广告的动作和动作计数各一次批量读取
+    async fn decide(&self, ctx: &PredictContext<'_>, signals: &[AdSignals]) -> Vec<Decision> {
+        let cfg = self.ads_dao.get_bandit_cfg();
+        let ad_ids: Vec<i64> = signals.iter().map(|s| s.ad_id).collect();
+        let all_arms: Vec<Vec<(String, f64)>> = self
+            .ads_dao
+            .get_multi_target_ctr_actions(&ad_ids)
+            .await
+            .into_iter()
+            .zip(signals)
+            .map(|(actions, signal)| arms(signal, actions))
+            .collect();
+        let keys: Vec<(i64, &str)> = signals
+            .iter()
+            .zip(&all_arms)
+            .flat_map(|(signal, arms)| arms.iter().map(move |(id, _)| (signal.ad_id, id.as_str())))
+            .collect();
+        let mut all_events = self.ads_dao.get_bandit_events(&keys).await.into_iter();
+        // 每个请求只检查一次先验
+        let prior = match self.policy {
+            BanditPolicy::Thompson => self.prior(ctx.ab_params),
+            _ => DEFAULT_PRIOR,
+        };
+
+        let mut decisions = Vec::with_capacity(signals.len());
+        for (signal, arms) in signals.iter().zip(&all_arms) {
+            let events: Vec<AdEvent> = all_events.by_ref().take(arms.len()).collect();
+            let explain = match self.choose(&events, ctx.ab_params, prior, &cfg) {
+                Some((i, score)) => {
+                    let is_exp_group = signal.exp_cfg.is_exp_group(ctx.usergroup);
+                    AdExplain {
+                        action_id: Some(arms[i].0.clone()),
+                        action_score: Some(score),
+                        ..self
+                            .rule
+                            .evaluate_target(ctx, signal, is_exp_group, arms[i].1)
+                    }
+                }
+                // 没有可选动作时与规则策略一致
+                None => self.rule.evaluate(ctx, signal),
+            };
+            decisions.push(self.rule.settle(ctx, self.name, signal.ad_id, explain));
+        }
+        decisions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::dao::MemoryStorage;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn event(show: i64, click: i64) -> AdEvent {
+        AdEvent {
+            show,
+            click,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_arms() {
+        let mut signal = AdSignals {
+            ad_id: 1,
+            ..Default::default()
+        };
+        let actions = BTreeMap::from([("x".to_string(), 0.1), ("y".to_string(), 0.2)]);
+        assert_eq!(arms(&signal, actions.clone()).len(), 2);
+        assert!(arms(&signal, BTreeMap::new()).is_empty());
+
+        // 没有配置动作时使用实验配置的动作
+        signal.exp_cfg = AdIdExpCfg {
+            ad_id: 1,
+            version: "1".to_string(),
+            main_action_id: "a".to_string(),
+            main_action_value: 0.1,
+            eg_action_id: "b".to_string(),
+            exp_action_value: 0.2,
+            ..Default::default()
+        };
+        assert_eq!(
+            arms(&signal, BTreeMap::new()),
+            vec![("a".to_string(), 0.1), ("b".to_string(), 0.2)]
+        );
+        assert_eq!(arms(&signal, actions)[0].0, "x");
+    }
+
+    #[test]
+    fn test_beta_posterior() {
+        let event = event(100, 10);
+        assert_eq!(beta_posterior(&event, DEFAULT_PRIOR), (11.0, 91.0));
+
+        let ab_params = AbParams {
+            click_a: 2.0,
+            click_b: 9.0,
+            ..Default::default()
+        };
+        let (alpha, beta) = beta_posterior(&event, beta_prior(&ab_params).unwrap());
+        assert_eq!((alpha, beta), (12.0, 98.0));
+        assert_eq!(alpha / (alpha + beta), event.get_click_rate(&ab_params));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_invalid_prior() {
+        assert_eq!(beta_prior(&AbParams::default()), None);
+        let ab_params = AbParams {
+            click_a: 5.0,
+            click_b: 1.0,
+            ..Default::default()
+        };
+        assert_eq!(beta_prior(&ab_params), None);
+
+        let strategy = BanditStrategy::thompson(AdsDB::new(Arc::new(MemoryStorage::new())).await);
+        assert_eq!(strategy.prior(&ab_params), DEFAULT_PRIOR);
+        assert_eq!(*strategy.invalid_prior.lock().unwrap(), Some((5.0, 1.0)));
+        let valid = AbParams {
+            click_a: 2.0,
+            click_b: 9.0,
+            ..Default::default()
+        };
+        assert_eq!(strategy.prior(&valid), (2.0, 8.0));
+    }
+
+    #[test]
+    fn test_sample_arm_prefers_better_action() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let posteriors = [(1.0 + 10.0, 1.0 + 990.0), (1.0 + 100.0, 1.0 + 900.0)];
+        let wins = (0..1000)
+            .filter(|_| sample_arm(&mut rng, &posteriors).unwrap().0 == 1)
+            .count();
+        assert!(wins > 990);
+
+        // 没有数据时各动作机会均等
+        let wins = (0..1000)
+            .filter(|_| sample_arm(&mut rng, &[(1.0, 1.0), (1.0, 1.0)]).unwrap().0 == 1)
+            .count();
+        assert!((400..600).contains(&wins));
+        assert!(sample_arm(&mut rng, &[]).is_none());
+    }
+
+    #[test]
+    fn test_ucb1_arm() {
+        let ab_params = AbParams::default();
+        // 没有展示的动作优先
+        let (i, score) = ucb1_arm(&[event(100, 10), event(0, 0)], &ab_params, 1.0).unwrap();
+        assert_eq!(i, 1);
+        assert!(score.is_infinite());
+
+        // 点击率高的动作胜出
+        let events = [event(1000, 10), event(1000, 100)];
+        assert_eq!(ucb1_arm(&events, &ab_params, 1.0).unwrap().0, 1);
+        // 展示少的动作获得更大的探索加成
+        let events = [event(10000, 500), event(10, 0)];
+        assert_eq!(ucb1_arm(&events, &ab_params, 0.0).unwrap().0, 0);
+        assert_eq!(ucb1_arm(&events, &ab_params, 2.0).unwrap().0, 1);
+        assert!(ucb1_arm(&[], &ab_params, 1.0).is_none());
+    }
+
+    #[test]
+    fn test_epsilon_greedy_arm() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let ab_params = AbParams::default();
+        let events = [event(1000, 10), event(1000, 100), event(1000, 50)];
+        for _ in 0..100 {
+            assert_eq!(
+                epsilon_greedy_arm(&mut rng, &events, &ab_params, 0.0)
+                    .unwrap()
+                    .0,
+                1
+            );
+        }
+        let explored = (0..3000)
+            .filter(|_| {
+                epsilon_greedy_arm(&mut rng, &events, &ab_params, 1.0)
+                    .unwrap()
+                    .0
+                    != 1
+            })
+            .count();
+        assert!((1800..2200).contains(&explored));
+        assert!(epsilon_greedy_arm(&mut rng, &[], &ab_params, 0.5).is_none());
+    }
+}
@@ -0,0 +1,255 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use rand::Rng;
+
+use crate::dao::AdsDB;
+use crate::model::*;
+
+pub mod bandit;
+pub mod rule;
+
+pub use bandit::*;
+pub use rule::*;
+
+/// 未指定且没有配置默认策略时使用
+pub const DEFAULT_STRATEGY: &str = "rule";
+
+/// 一次预估请求内所有策略共享的输入
+pub struct PredictContext<'a> {
+    pub request: &'a Request,
+    pub usergroup: &'a str,
+    pub exp_base_cfg: &'a ExpBaseCfg,
+    pub ab_params: &'a AbParams,
+    pub ctr_control: &'a CtrControlCfg,
+    /// 用户当日诱导点击次数
+    pub tempt_click: f64,
+    pub tempt_click_table: Arc<RangeTable>,
+    pub fill_rate_table: Arc<RangeTable>,
+    pub show_rate_table: Arc<RangeTable>,
+    pub click_rate_table: Arc<RangeTable>,
+    /// 是否需要返回决策过程
+    pub debug: bool,
+    pub draw_mode: DrawMode,
+    /// 当前时间所在的时间桶, 确定性抽样时参与计算
+    pub time_bucket: i64,
+    /// 本次请求各广告已抽取的随机数, 影子策略复用线上策略的抽样
+    pub draws: Mutex<HashMap<i64, f64>>,
+}
+
+impl PredictContext<'_> {
+    /// [0, 1) 的随机数, 同一请求内每个广告只抽取一次, Seeded模式下同样的输入总是得到同样的结果
+    pub fn draw(&self, ad_id: i64) -> f64 {
+        *self
+            .draws
+            .lock()
+            .unwrap()
+            .entry(ad_id)
+            .or_insert_with(|| match self.draw_mode {
+                DrawMode::Random => rand::thread_rng().gen(),
+                DrawMode::Seeded => seeded_draw(
+                    &self.request.usr,
+                    ad_id,
+                    &self.exp_base_cfg.version,
+                    self.time_bucket,
+                    self.request.request_id.as_deref(),
+                ),
+            })
+    }
+}
+
+/// 按score从高到低的排名, 从1开始, 得分相同时保持原顺序
+pub fn rank(decisions: &[Decision]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..decisions.len()).collect();
+    order.sort_by(|&a, &b| decisions[b].score.total_cmp(&decisions[a].score));
+    let mut ranks = vec![0; decisions.len()];
+    for (i, &pos) in order.iter().enumerate() {
+        ranks[pos] = i + 1;
+    }
+    ranks
+}
+
+/// top-K模式: 排名前K且得分大于0的广告投放, 其余不投放, 不使用随机抽样
+pub fn select_top_k(decisions: &mut [Decision], ranks: &[usize], k: usize) {
+    for (decision, &rank) in decisions.iter_mut().zip(ranks) {
+        decision.value = (rank <= k && decision.score > 0.0) as u8;
+        decision.probability = None;
+    }
+}
+
+/// 时间桶编号: timestamp / bucket_secs
+pub fn time_bucket(timestamp: i64, bucket_secs: i64) -> i64 {
+    timestamp.div_euclid(bucket_secs.max(1))
+}
+
+/// md5(usr|ad_id|version|time_bucket|request_id) 的前8字节映射到 [0, 1)
+pub fn seeded_draw(
+    usr: &str,
+    ad_id: i64,
+    version: &str,
+    time_bucket: i64,
+    request_id: Option<&str>,
+) -> f64 {
+    let seed = format!(
+        "{}|{}|{}|{}|{}",
+        usr,
+        ad_id,
+        version,
+        time_bucket,
+        request_id.unwrap_or("")
+    );
+    let digest = md5::compute(seed);
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&digest[..8]);
+    // 取高53位, 保证结果严格小于1
+    (u64::from_be_bytes(bytes) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// 单个广告的决策结果
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decision {
+    pub ad_id: i64,
+    pub value: u8,
+    /// 策略给出的得分, 规则策略为total_rate
+    pub score: f64,
+    /// 抽样使用的随机数, 未抽样时为None
+    pub probability: Option<f64>,
+    /// bandit策略选中的动作
+    pub action_id: Option<String>,
+    /// ctx.debug为true时返回
+    pub explain: Option<AdExplain>,
+}
+
+/// 预估策略, 对一次请求的所有广告给出决策, 结果与signals一一对应
+#[async_trait]
+pub trait Strategy: Send + Sync {
+    fn name(&self) -> &str;
+
+    async fn decide(&self, ctx: &PredictContext<'_>, signals: &[AdSignals]) -> Vec<Decision>;
+}
+
+pub type StrategyRef = Arc<dyn Strategy>;
+
+/// 按名字注册的策略
+#[derive(Clone, Default)]
+pub struct StrategyRegistry {
+    strategies: BTreeMap<String, StrategyRef>,
+}
+
+impl StrategyRegistry {
+    /// 内置策略: rule, threshold, thompson, ucb1, epsilon_greedy, always, never
+    pub fn with_builtin(ads_dao: &AdsDB) -> Self {
+        let mut registry = Self::default();
+        registry.register(Arc::new(RuleStrategy::new()));
+        registry.register(Arc::new(RuleStrategy::threshold()));
+        registry.register(Arc::new(BanditStrategy::thompson(ads_dao.clone())));
+        registry.register(Arc::new(BanditStrategy::ucb1(ads_dao.clone())));
+        registry.register(Arc::new(BanditStrategy::epsilon_greedy(ads_dao.clone())));
+        registry.register(Arc::new(FixedStrategy::new("always", 1)));
+        registry.register(Arc::new(FixedStrategy::new("never", 0)));
+        registry
+    }
+
+    pub fn register(&mut self, strategy: StrategyRef) {
+        self.strategies
+            .insert(strategy.name().to_string(), strategy);
+    }
+
+    pub fn get(&self, name: &str) -> Option<StrategyRef> {
+        self.strategies.get(name).cloned()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.strategies.keys().cloned().collect()
+    }
+}
+
+/// 固定返回同一个值, 用于全量放开或关闭
+pub struct FixedStrategy {
+    name: String,
+    value: u8,
+}
+
+impl FixedStrategy {
+    pub fn new(name: &str, value: u8) -> Self {
+        Self {
+            name: name.to_string(),
+            value,
+        }
+    }
+}
+
+#[async_trait]
+impl Strategy for FixedStrategy {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn decide(&self, ctx: &PredictContext<'_>, signals: &[AdSignals]) -> Vec<Decision> {
+        signals
+            .iter()
+            .map(|signal| Decision {
+                ad_id: signal.ad_id,
+                value: self.value,
+                score: self.value as f64,
+                probability: None,
+                action_id: None,
+                explain: ctx.debug.then(|| AdExplain {
+                    strategy: self.name.clone(),
+                    usergroup: ctx.usergroup.to_string(),
+                    ..Default::default()
+                }),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seeded_draw() {
+        let a = seeded_draw("u1", 7, "1", 100, None);
+        assert_eq!(a, seeded_draw("u1", 7, "1", 100, None));
+        assert!((0.0..1.0).contains(&a));
+
+        assert_ne!(a, seeded_draw("u1", 7, "1", 100, Some("r1")));
+        assert_ne!(a, seeded_draw("u1", 7, "1", 101, None));
+        assert_ne!(a, seeded_draw("u1", 8, "1", 100, None));
+        assert_ne!(a, seeded_draw("u2", 7, "1", 100, None));
+        assert_ne!(a, seeded_draw("u1", 7, "2", 100, None));
+
+        assert_eq!(time_bucket(7199, 3600), 1);
+        assert_eq!(time_bucket(7200, 3600), 2);
+    }
+
+    #[test]
+    fn test_rank_and_top_k() {
+        let mut decisions: Vec<Decision> = [0.3, 0.9, 0.0, 0.3, 0.5]
+            .iter()
+            .enumerate()
+            .map(|(i, &score)| Decision {
+                ad_id: i as i64,
+                value: 0,
+                score,
+                probability: Some(0.1),
+                action_id: None,
+                explain: None,
+            })
+            .collect();
+        let ranks = rank(&decisions);
+        assert_eq!(ranks, vec![3, 1, 5, 4, 2]);
+
+        select_top_k(&mut decisions, &ranks, 3);
+        let values: Vec<u8> = decisions.iter().map(|d| d.value).collect();
+        assert_eq!(values, vec![1, 1, 0, 0, 1]);
+        assert!(decisions.iter().all(|d| d.probability.is_none()));
+
+        // 得分为0的广告不投放
+        select_top_k(&mut decisions, &ranks, 10);
+        assert_eq!(decisions[2].value, 0);
+        assert_eq!(decisions[3].value, 1);
+    }
+}
@@ -0,0 +1,149 @@
+use async_trait::async_trait;
+
+use super::{Decision, PredictContext, Strategy};
+use crate::model::*;
+
+/// 规则策略: 四个信号系数相乘得到total_rate, 按 `cfg:ctr:control` 以窗口CTR与目标CTR的偏差调整,
+/// 超过base_value后以total_rate为概率随机投放
+pub struct RuleStrategy {
+    name: &'static str,
+    /// 为false时超过base_value直接投放, 不做随机抽样
+    draw: bool,
+}
+
+impl RuleStrategy {
+    pub fn new() -> Self {
+        Self {
+            name: "rule",
+            draw: true,
+        }
+    }
+
+    pub fn threshold() -> Self {
+        Self {
+            name: "threshold",
+            draw: false,
+        }
+    }
+
+    /// 计算total_rate及所有中间值, 字符串字段留空由调用方按需填充
+    pub fn evaluate(&self, ctx: &PredictContext<'_>, signal: &AdSignals) -> AdExplain {
+        let ad_exp_cfg = &signal.exp_cfg;
+        let is_exp_group = ad_exp_cfg.is_exp_group(ctx.usergroup);
+
+        // 目标CTR
+        let target_ctr = if is_exp_group {
+            ad_exp_cfg.exp_action_value // 如果是试验组
+        } else {
+            ad_exp_cfg.main_action_value // 对照与主版本
+        };
+        self.evaluate_target(ctx, signal, is_exp_group, target_ctr)
+    }
+
+    /// 以指定的目标CTR计算total_rate
+    pub fn evaluate_target(
+        &self,
+        ctx: &PredictContext<'_>,
+        signal: &AdSignals,
+        is_exp_group: bool,
+        target_ctr: f64,
+    ) -> AdExplain {
+        let user_daily_ad_id_event = &signal.daily_event;
+        let fill_rate = user_daily_ad_id_event.get_fill_rate(ctx.ab_params);
+        let show_rate = user_daily_ad_id_event.get_show_rate(ctx.ab_params);
+        let click_rate = user_daily_ad_id_event.get_click_rate(ctx.ab_params);
+
+        let rate_a = ctx.tempt_click_table.lookup(ctx.tempt_click);
+        let rate_b = ctx.fill_rate_table.lookup(fill_rate);
+        let rate_c = ctx.show_rate_table.lookup(show_rate);
+        let rate_d = ctx.click_rate_table.lookup(click_rate);
+        let window_ctr = signal.realtime_event.get_click_rate_without_ab();
+
+        // 按窗口CTR与目标CTR的偏差调整, A加大投放, C减少投放
+        let adjust = ctx
+            .ctr_control
+            .resolve(&ctx.exp_base_cfg.version, signal.ad_id)
+            .adjust(window_ctr, target_ctr);
+        let total_rate = rate_a * rate_b * rate_c * rate_d * adjust.factor;
+
+        AdExplain {
+            is_exp_group,
+            tempt_click: ctx.tempt_click,
+            fill_rate,
+            show_rate,
+            click_rate,
+            bucket_a: ctx.tempt_click_table.find(ctx.tempt_click).cloned(),
+            bucket_b: ctx.fill_rate_table.find(fill_rate).cloned(),
+            bucket_c: ctx.show_rate_table.find(show_rate).cloned(),
+            bucket_d: ctx.click_rate_table.find(click_rate).cloned(),
+            rate_a,
+            rate_b,
+            rate_c,
+            rate_d,
+            window_ctr,
+            target_ctr,
+            branch: adjust.branch,
+            ctr_factor: adjust.factor,
+            ctr_tier: adjust.tier,
+            total_rate,
+            base_value: ctx.exp_base_cfg.base_value,
+            ..Default::default()
+        }
+    }
+
+    /// 超过base_value后以total_rate为概率抽样, 得到最终决策
+    pub fn settle(
+        &self,
+        ctx: &PredictContext<'_>,
+        strategy: &str,
+        ad_id: i64,
+        mut explain: AdExplain,
+    ) -> Decision {
+        // 随机预估
+        let value = if explain.total_rate >= explain.base_value {
+            if !self.draw {
+                1
+            } else {
+                let probability = ctx.draw(ad_id);
+                explain.draw = Some(probability);
+                if ctx.draw_mode == DrawMode::Seeded {
+                    explain.time_bucket = Some(ctx.time_bucket);
+                }
+                if explain.total_rate >= probability {
+                    1
+                } else {
+                    0
+                }
+            }
+        } else {
+            0
+        };
+
+        Decision {
+            ad_id,
+            value,
+            score: explain.total_rate,
+            probability: explain.draw,
+            action_id: explain.action_id.clone(),
+            explain: ctx.debug.then(|| AdExplain {
+                strategy: strategy.to_string(),
+                usergroup: ctx.usergroup.to_string(),
+                ..explain
+            }),
+        }
+    }
+}
+
+#[async_trait]
+impl Strategy for RuleStrategy {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    async fn decide(&self, ctx: &PredictContext<'_>, signals: &[AdSignals]) -> Vec<Decision> {
+        signals
+            .iter()
+            .map(|signal| self.settle(ctx, self.name, signal.ad_id, self.evaluate(ctx, signal)))
+            .collect()
+    }
+}
@@ -0,0 +1,617 @@
+use std::{
+    fmt::Display,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio_cron_scheduler::Job;
+
+use crate::dao::*;
+
+/// 环境变量前缀, 配置项 `redis.url` 对应 `SMARTY_REDIS_URL`
+pub const ENV_PREFIX: &str = "SMARTY_";
+/// 指定配置文件路径的环境变量
+pub const ENV_CONFIG_FILE: &str = "SMARTY_CONFIG";
+
+/// 支持通过环境变量和命令行覆盖的配置项
+pub const KEYS: &[&str] = &[
+    "server.addr",
+    "server.log_level",
+    "storage.backend",
+    "redis.url",
+    "redis.max_size",
+    "redis.min_idle",
+    "redis.connection_timeout_ms",
+    "redis.idle_timeout_secs",
+    "redis.test_on_check_out",
+    "cache.adid_ttl_secs",
+    "cache.adid_tti_secs",
+    "cache.exp_cfg_ttl_secs",
+    "cache.exp_cfg_tti_secs",
+    "dyn_cfg.sync_cron",
+    "events.window_secs",
+    "events.bucket_secs",
+    "events.utc_offset_secs",
+    "events.daily_ttl_secs",
+    "leader.node_id",
+    "leader.lease_ms",
+    "exp_driver.cron",
+];
+
+const LOG_LEVELS: &[&str] = &["error", "warn", "info", "debug", "trace"];
+
+/// 服务配置, 优先级从低到高: 默认值 < 配置文件 < 环境变量 < 命令行参数
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub server: ServerSettings,
+    pub storage: StorageSettings,
+    pub redis: RedisSettings,
+    pub cache: CacheSettings,
+    pub dyn_cfg: DynCfgSettings,
+    pub events: EventSettings,
+    pub leader: LeaderSettings,
+    pub exp_driver: ExpDriverSettings,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSettings {
+    pub addr: String,
+    /// RUST_LOG存在时以RUST_LOG为准
+    pub log_level: String,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        Self {
+            addr: "0.0.0.0:3000".to_string(),
+            log_level: "debug".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    Redis,
+    /// 进程内存储, 仅用于本地调试
+    Memory,
+}
+
+impl FromStr for StorageBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "redis" => Ok(StorageBackend::Redis),
+            "memory" => Ok(StorageBackend::Memory),
+            _ => bail!("expected redis or memory"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageSettings {
+    pub backend: StorageBackend,
+}
+
+impl Default for StorageSettings {
+    fn default() -> Self {
+        Self {
+            backend: StorageBackend::Redis,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedisSettings {
+    pub url: String,
+    pub max_size: u32,
+    pub min_idle: Option<u32>,
+    pub connection_timeout_ms: u64,
+    /// 0表示不回收空闲连接
+    pub idle_timeout_secs: u64,
+    pub test_on_check_out: bool,
+}
+
+impl Default for RedisSettings {
+    fn default() -> Self {
+        let cfg = RedisPoolCfg::default();
+        Self {
+            url: cfg.url,
+            max_size: cfg.max_size,
+            min_idle: cfg.min_idle,
+            connection_timeout_ms: cfg.connection_timeout.as_millis() as u64,
+            idle_timeout_secs: cfg.idle_timeout.map_or(0, |d| d.as_secs()),
+            test_on_check_out: cfg.test_on_check_out,
+        }
+    }
+}
+
+impl RedisSettings {
+    pub fn pool_cfg(&self) -> RedisPoolCfg {
+        RedisPoolCfg {
+            url: self.url.clone(),
+            max_size: self.max_size,
+            min_idle: self.min_idle,
+            connection_timeout: Duration::from_millis(self.connection_timeout_ms),
+            idle_timeout: match self.idle_timeout_secs {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            test_on_check_out: self.test_on_check_out,
+        }
+    }
+}
+
+/// 本地缓存过期时间
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheSettings {
+    pub adid_ttl_secs: u64,
+    pub adid_tti_secs: u64,
+    pub exp_cfg_ttl_secs: u64,
+    pub exp_cfg_tti_secs: u64,
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        let cfg = AdsDBCfg::default();
+        Self {
+            adid_ttl_secs: cfg.adid_cache_ttl.as_secs(),
+            adid_tti_secs: cfg.adid_cache_tti.as_secs(),
+            exp_cfg_ttl_secs: cfg.exp_cfg_cache_ttl.as_secs(),
+            exp_cfg_tti_secs: cfg.exp_cfg_cache_tti.as_secs(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DynCfgSettings {
+    /// 动态配置同步周期, 6段cron(含秒)
+    pub sync_cron: String,
+}
+
+impl Default for DynCfgSettings {
+    fn default() -> Self {
+        Self {
+            sync_cron: DYN_CFG_SYNC_CRON.to_string(),
+        }
+    }
+}
+
+/// 事件计数配置
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EventSettings {
+    pub window_secs: i64,
+    pub bucket_secs: i64,
+    /// 切分自然日的时区, 不配置时使用本机时区
+    pub utc_offset_secs: Option<i32>,
+    pub daily_ttl_secs: usize,
+}
+
+impl Default for EventSettings {
+    fn default() -> Self {
+        let window = RealtimeWindow::default();
+        Self {
+            window_secs: window.bucket_secs * window.buckets,
+            bucket_secs: window.bucket_secs,
+            utc_offset_secs: None,
+            daily_ttl_secs: DailyBucket::default().ttl_secs,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LeaderSettings {
+    /// 不配置时使用 主机名-进程号
+    pub node_id: Option<String>,
+    pub lease_ms: u64,
+}
+
+impl Default for LeaderSettings {
+    fn default() -> Self {
+        Self {
+            node_id: None,
+            lease_ms: 15_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExpDriverSettings {
+    pub cron: String,
+}
+
+impl Default for ExpDriverSettings {
+    fn default() -> Self {
+        Self {
+            cron: crate::service::EXP_DRIVER_CRON.to_string(),
+        }
+    }
+}
+
+/// 命令行参数: `--config <path>` 指定配置文件, `--<key> <value>` 或 `--<key>=<value>` 覆盖配置项
+#[derive(Debug, Default)]
+pub struct Cli {
+    pub config: Option<PathBuf>,
+    pub help: bool,
+    pub overrides: Vec<(String, String)>,
+}
+
+impl Cli {
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self> {
+        let mut cli = Cli::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "-h" || arg == "--help" {
+                cli.help = true;
+                continue;
+            }
+            let flag = match arg.strip_prefix("--") {
+                Some(flag) => flag,
+                None if arg == "-c" => "config",
+                None => bail!("unexpected argument: {}", arg),
+            };
+            let (key, value) = match flag.split_once('=') {
+                Some((key, value)) => (key.to_string(), value.to_string()),
+                None => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| anyhow!("missing value for --{}", flag))?;
+                    (flag.to_string(), value)
+                }
+            };
+            if key == "config" {
+                cli.config = Some(PathBuf::from(value));
+            } else if KEYS.contains(&key.as_str()) {
+                cli.overrides.push((key, value));
+            } else {
+                bail!("unknown option: --{}", key);
+            }
+        }
+        Ok(cli)
+    }
+
+    pub fn usage() -> String {
+        let mut usage = String::from(
+            "usage: smarty-adserver [--config <file>] [--<key> <value>]...\n\n\
+             precedence: defaults < config file < environment < command line\n\
+             config file: --config / -c, or SMARTY_CONFIG\n\nkeys:\n",
+        );
+        for key in KEYS {
+            usage.push_str(&format!("  --{:<28} {}\n", key, env_name(key)));
+        }
+        usage
+    }
+}
+
+/// 配置项对应的环境变量名
+pub fn env_name(key: &str) -> String {
+    format!("{}{}", ENV_PREFIX, key.replace('.', "_").to_uppercase())
+}
+
+fn parse_value<T>(key: &str, value: &str) -> Result<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value
+        .trim()
+        .parse()
+        .map_err(|e| anyhow!("invalid value {:?} for {}: {}", value, key, e))
+}
+
+/// 空字符串表示不配置
+fn parse_opt<T>(key: &str, value: &str) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if value.trim().is_empty() {
+        Ok(None)
+    } else {
+        parse_value(key, value).map(Some)
+    }
+}
+
+fn check_cron(errors: &mut Vec<String>, key: &str, cron: &str) {
+    if Job::new(cron, |_, _| {}).is_err() {
+        errors.push(format!("{}: invalid cron expression {:?}", key, cron));
+    }
+}
+
+impl Settings {
+    /// 按优先级合并配置并校验
+    pub fn load<F>(cli: &Cli, env: F) -> Result<Self>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let config = cli
+            .config
+            .clone()
+            .or_else(|| env(ENV_CONFIG_FILE).map(PathBuf::from));
+        let mut settings = match config {
+            Some(path) => Self::from_file(&path)?,
+            None => Self::default(),
+        };
+
+        for key in KEYS {
+            let name = env_name(key);
+            if let Some(value) = env(&name) {
+                settings
+                    .set(key, &value)
+                    .with_context(|| format!("environment variable {}", name))?;
+            }
+        }
+        for (key, value) in &cli.overrides {
+            settings
+                .set(key, value)
+                .with_context(|| format!("command line option --{}", key))?;
+        }
+
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("read config file {}", path.display()))?;
+        Self::from_toml(&content).with_context(|| format!("parse config file {}", path.display()))
+    }
+
+    pub fn from_toml(content: &str) -> Result<Self> {
+        Ok(toml::from_str(content)?)
+    }
+
+    /// 按key设置单个配置项
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "server.addr" => self.server.addr = value.trim().to_string(),
+            "server.log_level" => self.server.log_level = value.trim().to_lowercase(),
+            "storage.backend" => self.storage.backend = parse_value(key, value)?,
+            "redis.url" => self.redis.url = value.trim().to_string(),
+            "redis.max_size" => self.redis.max_size = parse_value(key, value)?,
+            "redis.min_idle" => self.redis.min_idle = parse_opt(key, value)?,
+            "redis.connection_timeout_ms" => {
+                self.redis.connection_timeout_ms = parse_value(key, value)?
+            }
+            "redis.idle_timeout_secs" => self.redis.idle_timeout_secs = parse_value(key, value)?,
+            "redis.test_on_check_out" => self.redis.test_on_check_out = parse_value(key, value)?,
+            "cache.adid_ttl_secs" => self.cache.adid_ttl_secs = parse_value(key, value)?,
+            "cache.adid_tti_secs" => self.cache.adid_tti_secs = parse_value(key, value)?,
+            "cache.exp_cfg_ttl_secs" => self.cache.exp_cfg_ttl_secs = parse_value(key, value)?,
+            "cache.exp_cfg_tti_secs" => self.cache.exp_cfg_tti_secs = parse_value(key, value)?,
+            "dyn_cfg.sync_cron" => self.dyn_cfg.sync_cron = value.trim().to_string(),
+            "events.window_secs" => self.events.window_secs = parse_value(key, value)?,
+            "events.bucket_secs" => self.events.bucket_secs = parse_value(key, value)?,
+            "events.utc_offset_secs" => self.events.utc_offset_secs = parse_opt(key, value)?,
+            "events.daily_ttl_secs" => self.events.daily_ttl_secs = parse_value(key, value)?,
+            "leader.node_id" => self.leader.node_id = parse_opt(key, value)?,
+            "leader.lease_ms" => self.leader.lease_ms = parse_value(key, value)?,
+            "exp_driver.cron" => self.exp_driver.cron = value.trim().to_string(),
+            _ => bail!("unknown setting: {}", key),
+        }
+        Ok(())
+    }
+
+    /// 一次性返回所有不合法的配置项
+    pub fn validate(&self) -> Result<()> {
+        let mut errors = Vec::new();
+
+        if self.server.addr.parse::<SocketAddr>().is_err() {
+            errors.push(format!(
+                "server.addr: {:?} is not a socket address",
+                self.server.addr
+            ));
+        }
+        if !LOG_LEVELS.contains(&self.server.log_level.as_str()) {
+            errors.push(format!(
+                "server.log_level: {:?} is not one of {}",
+                self.server.log_level,
+                LOG_LEVELS.join("/")
+            ));
+        }
+
+        if self.storage.backend == StorageBackend::Redis {
+            if let Err(e) = redis::Client::open(self.redis.url.as_str()) {
+                errors.push(format!("redis.url: {:?} {}", self.redis.url, e));
+            }
+        }
+        if self.redis.max_size == 0 {
+            errors.push("redis.max_size: must be greater than 0".to_string());
+        }
+        if let Some(min_idle) = self.redis.min_idle {
+            if min_idle > self.redis.max_size {
+                errors.push(format!(
+                    "redis.min_idle: {} is greater than redis.max_size {}",
+                    min_idle, self.redis.max_size
+                ));
+            }
+        }
+        if self.redis.connection_timeout_ms == 0 {
+            errors.push("redis.connection_timeout_ms: must be greater than 0".to_string());
+        }
+
+        for (key, value) in [
+            ("cache.adid_ttl_secs", self.cache.adid_ttl_secs),
+            ("cache.adid_tti_secs", self.cache.adid_tti_secs),
+            ("cache.exp_cfg_ttl_secs", self.cache.exp_cfg_ttl_secs),
+            ("cache.exp_cfg_tti_secs", self.cache.exp_cfg_tti_secs),
+        ] {
+            if value == 0 {
+                errors.push(format!("{}: must be greater than 0", key));
+            }
+        }
+
+        check_cron(&mut errors, "dyn_cfg.sync_cron", &self.dyn_cfg.sync_cron);
+        check_cron(&mut errors, "exp_driver.cron", &self.exp_driver.cron);
+
+        if self.events.bucket_secs <= 0 {
+            errors.push("events.bucket_secs: must be greater than 0".to_string());
+        } else if self.events.window_secs < self.events.bucket_secs {
+            errors.push(format!(
+                "events.window_secs: {} is shorter than events.bucket_secs {}",
+                self.events.window_secs, self.events.bucket_secs
+            ));
+        } else if self.events.window_secs % self.events.bucket_secs != 0 {
+            // 窗口按整桶计算, 不是整数倍时实际窗口会被截短
+            errors.push(format!(
+                "events.window_secs: {} is not a multiple of events.bucket_secs {}",
+                self.events.window_secs, self.events.bucket_secs
+            ));
+        }
+        if let Some(offset) = self.events.utc_offset_secs {
+            if offset.abs() >= 24 * 3600 {
+                errors.push(format!(
+                    "events.utc_offset_secs: {} is out of range",
+                    offset
+                ));
+            }
+        }
+        if self.events.daily_ttl_secs < 24 * 3600 {
+            errors.push("events.daily_ttl_secs: must cover at least one day".to_string());
+        }
+
+        if self.leader.lease_ms < 300 {
+            errors.push("leader.lease_ms: must be at least 300".to_string());
+        }
+
+        if !errors.is_empty() {
+            bail!("invalid settings:\n  {}", errors.join("\n  "));
+        }
+        Ok(())
+    }
+
+    pub fn ads_db_cfg(&self) -> AdsDBCfg {
+        let daily_bucket = match self.events.utc_offset_secs {
+            Some(offset) => DailyBucket::new(offset, self.events.daily_ttl_secs),
+            None => DailyBucket {
+                ttl_secs: self.events.daily_ttl_secs,
+                ..Default::default()
+            },
+        };
+        AdsDBCfg {
+            adid_cache_ttl: Duration::from_secs(self.cache.adid_ttl_secs),
+            adid_cache_tti: Duration::from_secs(self.cache.adid_tti_secs),
+            exp_cfg_cache_ttl: Duration::from_secs(self.cache.exp_cfg_ttl_secs),
+            exp_cfg_cache_tti: Duration::from_secs(self.cache.exp_cfg_tti_secs),
+            dyn_cfg_sync_cron: self.dyn_cfg.sync_cron.clone(),
+            realtime_window: RealtimeWindow::new(self.events.window_secs, self.events.bucket_secs),
+            daily_bucket,
+        }
+    }
+
+    pub fn leader_lease(&self) -> Duration {
+        Duration::from_millis(self.leader.lease_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_default_settings_valid() {
+        let settings = Settings::default();
+        settings.validate().unwrap();
+        assert_eq!(settings.server.addr, "0.0.0.0:3000");
+        assert_eq!(settings.redis.url, "redis://127.0.0.1");
+        assert_eq!(settings.cache.adid_ttl_secs, 24 * 3600);
+        assert_eq!(settings.dyn_cfg.sync_cron, "*/10 * * * * *");
+    }
+
+    #[test]
+    fn test_every_key_settable() {
+        let mut settings = Settings::default();
+        for key in KEYS {
+            let err = settings.set(key, "").err().map(|e| e.to_string());
+            assert!(
+                !err.unwrap_or_default().starts_with("unknown setting"),
+                "{}",
+                key
+            );
+        }
+    }
+
+    #[test]
+    fn test_precedence() {
+        let path = std::env::temp_dir().join(format!("smarty-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"
+[server]
+addr = "127.0.0.1:4000"
+log_level = "info"
+
+[redis]
+url = "redis://file:6379"
+max_size = 8
+"#,
+        )
+        .unwrap();
+
+        let env = HashMap::from([
+            (ENV_CONFIG_FILE.to_string(), path.display().to_string()),
+            (
+                "SMARTY_REDIS_URL".to_string(),
+                "redis://env:6379".to_string(),
+            ),
+            ("SMARTY_SERVER_LOG_LEVEL".to_string(), "warn".to_string()),
+        ]);
+        let cli = Cli::parse(args(&["--server.log_level", "error", "--redis.max_size=4"])).unwrap();
+        let settings = Settings::load(&cli, |k| env.get(k).cloned()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(settings.server.addr, "127.0.0.1:4000");
+        assert_eq!(settings.redis.url, "redis://env:6379");
+        assert_eq!(settings.server.log_level, "error");
+        assert_eq!(settings.redis.max_size, 4);
+        // 未配置的项保持默认值
+        assert_eq!(settings.cache.exp_cfg_ttl_secs, 24 * 3600);
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        assert!(Cli::parse(args(&["--no.such.key", "1"])).is_err());
+        assert!(Settings::from_toml("[server]\nport = 3000\n").is_err());
+
+        let cli = Cli::parse(args(&[
+            "--server.addr",
+            "localhost",
+            "--events.bucket_secs",
+            "0",
+            "--dyn_cfg.sync_cron",
+            "every 10s",
+        ]))
+        .unwrap();
+        let err = Settings::load(&cli, |_| None).unwrap_err().to_string();
+        assert!(err.contains("server.addr"));
+        assert!(err.contains("events.bucket_secs"));
+        assert!(err.contains("dyn_cfg.sync_cron"));
+
+        let cli = Cli::parse(args(&["--events.window_secs", "3630"])).unwrap();
+        let err = Settings::load(&cli, |_| None).unwrap_err().to_string();
+        assert!(err.contains("not a multiple of events.bucket_secs"));
+
+        let cli = Cli::parse(args(&["--redis.max_size", "many"])).unwrap();
+        assert!(Settings::load(&cli, |_| None).is_err());
+    }
+}